@@ -0,0 +1,349 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use k8s_openapi::{
+    api::{
+        apps::v1::StatefulSet,
+        networking::v1::{Ingress, IngressBackend, IngressServiceBackend, ServiceBackendPort},
+    },
+    serde_json,
+};
+use kube::{
+    api::{Patch, PatchParams},
+    Api,
+};
+use log::{info, warn};
+use rocket::http::Status;
+
+use crate::context::Context;
+use crate::kubernetes::{instance_phase, kubernetes_scale_instance, wait_for_instance_ready};
+use crate::metrics::metrics;
+use crate::models::database::DatabasePhase;
+use crate::models::error::{error_response, ErrorResponse};
+
+/// Annotation of the Ingress keeping its rules while it points at the activator.
+const INGRESS_RULES_ANNOTATION: &str = "moonscale/ingress-rules";
+/// How long a request is held while its instance wakes up.
+const ACTIVATOR_WAKE_TIMEOUT: Duration = Duration::from_secs(3 * 60);
+const ACTIVATOR_PROXY_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long an instance found ready is trusted to still be, without checking it.
+const ACTIVATOR_READY_TTL: Duration = Duration::from_secs(10);
+/// Port of the PlanetScale HTTP API served by the instance, see the template.
+const INSTANCE_API_PORT: u16 = 3900;
+/// Headers describing a single connection, they are never forwarded.
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+];
+
+/// The Service in the instances namespace routing to moonscale, which the Ingress
+/// of a paused instance points at.
+#[derive(Clone)]
+pub struct ActivatorConfig {
+    pub service: String,
+    pub port: i32,
+}
+
+/// The response of an instance to a forwarded request.
+pub struct ProxiedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+pub fn is_hop_by_hop_header(name: &str) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+/// Forwards the requests held by the activator, or received by the built-in proxy,
+/// to the instances once they are awake.
+pub struct Activator {
+    http: reqwest::Client,
+    /// When each instance was last found ready.
+    ready: Mutex<HashMap<String, Instant>>,
+}
+
+impl Default for Activator {
+    fn default() -> Self {
+        Activator {
+            http: reqwest::Client::builder()
+                .timeout(ACTIVATOR_PROXY_TIMEOUT)
+                .build()
+                .expect("Failed to build the activator HTTP client"),
+            ready: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Activator {
+    fn is_known_ready(&self, instance: &str) -> bool {
+        self.ready
+            .lock()
+            .unwrap()
+            .get(instance)
+            .is_some_and(|checked| checked.elapsed() < ACTIVATOR_READY_TTL)
+    }
+
+    fn set_ready(&self, instance: &str, ready: bool) {
+        let mut known_ready = self.ready.lock().unwrap();
+
+        if ready {
+            known_ready.insert(instance.to_owned(), Instant::now());
+        } else {
+            known_ready.remove(instance);
+        }
+        known_ready.retain(|_, checked| checked.elapsed() < ACTIVATOR_READY_TTL);
+    }
+
+    /// Send the request to the PlanetScale HTTP API of the instance and return its
+    /// response, `path` includes the query string.
+    pub async fn forward(
+        &self,
+        context: &Context,
+        instance: &str,
+        method: &str,
+        path: &str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> Result<ProxiedResponse, ErrorResponse> {
+        let url = format!(
+            "http://moonscale-instance-{}-ps.{}.svc:{}{}",
+            instance, context.config.namespace, INSTANCE_API_PORT, path
+        );
+        let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|_| {
+            error_response(
+                Status::MethodNotAllowed,
+                "method_not_allowed",
+                format!("Unsupported method {}", method),
+            )
+        })?;
+        let mut builder = self.http.request(method, url).body(body);
+
+        for (name, value) in headers {
+            // The length of the forwarded body is set by the client
+            if !is_hop_by_hop_header(&name) && !name.eq_ignore_ascii_case("content-length") {
+                builder = builder.header(name, value);
+            }
+        }
+        let bad_gateway = |err: reqwest::Error| {
+            warn!(
+                "Failed to forward a request to instance {}: {}",
+                instance, err
+            );
+            error_response(
+                Status::BadGateway,
+                "instance_unreachable",
+                format!("Instance {} didn't answer", instance),
+            )
+        };
+        let response = builder.send().await.map_err(bad_gateway)?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !is_hop_by_hop_header(name.as_str()))
+            // The length of the body is set again when responding
+            .filter(|(name, _)| *name != reqwest::header::CONTENT_LENGTH)
+            .filter_map(|(name, value)| {
+                Some((name.as_str().to_owned(), value.to_str().ok()?.to_owned()))
+            })
+            .collect();
+        let body = response.bytes().await.map_err(bad_gateway)?.to_vec();
+
+        Ok(ProxiedResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Resume the instance if it's paused and wait until it accepts connections, its
+/// Ingress then points at the instance again. Running instances are left as is, so
+/// the built-in proxy can call this on every request.
+pub async fn wake_instance(context: &Context, instance: &str) -> Result<(), ErrorResponse> {
+    if context.activator.is_known_ready(instance) {
+        return Ok(());
+    }
+    let unavailable = |message: String| {
+        error_response(Status::ServiceUnavailable, "instance_unavailable", message)
+    };
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let sts = api_sts
+        .get_opt(&format!("moonscale-instance-{}", instance))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "StatefulSet", started);
+    let sts = sts
+        .map_err(|err| unavailable(format!("Failed to get instance {}: {}", instance, err)))?
+        .ok_or_else(|| {
+            error_response(
+                Status::NotFound,
+                "instance_not_found",
+                format!("Instance {} doesn't exist", instance),
+            )
+        })?;
+
+    match instance_phase(&sts) {
+        DatabasePhase::Ready => {
+            if context.config.activator.is_some() {
+                switch_ingress(context, instance, false).await;
+            }
+            context.activator.set_ready(instance, true);
+            return Ok(());
+        }
+        DatabasePhase::Terminating => {
+            return Err(unavailable(format!(
+                "Instance {} is being deleted",
+                instance
+            )))
+        }
+        DatabasePhase::Paused => {
+            info!(instance = instance; "Waking instance {} up", instance);
+            kubernetes_scale_instance(&context.kubernetes_client, instance, false)
+                .await
+                .map_err(|err| {
+                    unavailable(format!("Failed to resume instance {}: {}", instance, err))
+                })?;
+        }
+        DatabasePhase::Provisioning => {}
+    }
+    let ready = rocket::tokio::time::timeout(
+        ACTIVATOR_WAKE_TIMEOUT,
+        wait_for_instance_ready(&context.kubernetes_client, instance),
+    )
+    .await;
+
+    match ready {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) | Err(_) => {
+            return Err(error_response(
+                Status::GatewayTimeout,
+                "instance_wake_timeout",
+                format!("Instance {} didn't wake up in time", instance),
+            ))
+        }
+        Ok(Err(err)) => {
+            return Err(unavailable(format!(
+                "Failed to watch instance {}: {}",
+                instance, err
+            )))
+        }
+    }
+    if context.config.activator.is_some() {
+        switch_ingress(context, instance, false).await;
+    }
+    context.activator.set_ready(instance, true);
+    Ok(())
+}
+
+/// Point the Ingress of the instance at the activator while it's paused, and back at
+/// the instance once it's resumed. Failures are only logged, the instance keeps
+/// working through its API, only waking up on request is affected.
+pub async fn switch_ingress(context: &Context, instance: &str, paused: bool) {
+    if paused {
+        context.activator.set_ready(instance, false);
+    }
+    let result = match (paused, &context.config.activator) {
+        (true, Some(activator)) => route_ingress(context, instance, Some(activator)).await,
+        (true, None) => Ok(()),
+        (false, _) => route_ingress(context, instance, None).await,
+    };
+
+    if let Err(err) = result {
+        warn!(
+            instance = instance;
+            "Failed to switch the ingress of instance {}: {:#}", instance, err
+        );
+    }
+}
+
+async fn route_ingress(
+    context: &Context,
+    instance: &str,
+    activator: Option<&ActivatorConfig>,
+) -> Result<(), anyhow::Error> {
+    let api: Api<Ingress> = Api::default_namespaced(context.kubernetes_client.clone());
+    let name = format!("moonscale-instance-{}", instance);
+    let started = Instant::now();
+    let ingress = api.get_opt(&name).await;
+
+    metrics().observe_kubernetes_call("get", "Ingress", started);
+    let Some(ingress) = ingress? else {
+        return Ok(());
+    };
+    let saved_rules = ingress
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(INGRESS_RULES_ANNOTATION));
+    let patch = match (activator, saved_rules) {
+        // Already pointing at the activator, keep the rules saved the first time
+        (Some(_), Some(_)) | (None, None) => return Ok(()),
+        (Some(activator), None) => {
+            let rules = ingress
+                .spec
+                .as_ref()
+                .and_then(|spec| spec.rules.clone())
+                .unwrap_or_default();
+            let mut activator_rules = rules.clone();
+            let backend = IngressBackend {
+                service: Some(IngressServiceBackend {
+                    name: activator.service.clone(),
+                    port: Some(ServiceBackendPort {
+                        number: Some(activator.port),
+                        name: None,
+                    }),
+                }),
+                resource: None,
+            };
+
+            for path in activator_rules
+                .iter_mut()
+                .filter_map(|rule| rule.http.as_mut())
+                .flat_map(|http| http.paths.iter_mut())
+            {
+                path.backend = backend.clone();
+            }
+            serde_json::json!({
+                "metadata": { "annotations": {
+                    INGRESS_RULES_ANNOTATION: serde_json::to_string(&rules)?
+                } },
+                "spec": { "rules": activator_rules }
+            })
+        }
+        (None, Some(saved_rules)) => {
+            let rules: serde_json::Value = serde_json::from_str(saved_rules)?;
+
+            serde_json::json!({
+                "metadata": { "annotations": { INGRESS_RULES_ANNOTATION: null } },
+                "spec": { "rules": rules }
+            })
+        }
+    };
+    let started = Instant::now();
+    let result = api
+        .patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await;
+
+    metrics().observe_kubernetes_call("patch", "Ingress", started);
+    result?;
+    info!(
+        instance = instance;
+        "Pointed the ingress of instance {} at the {}",
+        instance,
+        if activator.is_some() { "activator" } else { "instance" }
+    );
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Instant, SystemTime},
+};
+
+use k8s_openapi::serde_json;
+use kube::{runtime::events::EventType, Client};
+use log::warn;
+use rocket::http::Status;
+use rocket::tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::{
+    kubernetes::publish_instance_event,
+    models::audit::{AuditAction, AuditOutcome, AuditRecordModel},
+};
+
+#[derive(Clone)]
+pub enum AuditSink {
+    /// Print every record as a JSON line on stdout.
+    Stdout,
+    /// Append every record as a JSON line to the given file.
+    File(PathBuf),
+    /// Publish every record as a Kubernetes Event attached to the instance.
+    KubernetesEvents,
+}
+
+/// Filters applied when querying the audit history, every field is optional.
+#[derive(Default)]
+pub struct AuditFilter<'a> {
+    pub principal: Option<&'a str>,
+    pub action: Option<&'a str>,
+    pub instance: Option<&'a str>,
+    pub outcome: Option<&'a str>,
+    pub since: Option<SystemTime>,
+    pub limit: Option<usize>,
+}
+
+pub struct AuditLog {
+    sinks: Vec<AuditSink>,
+    history_size: usize,
+    history: Mutex<VecDeque<AuditRecordModel>>,
+}
+
+/// Build an audit record for a call that started at `started` and returned `status`.
+pub fn audit_record(
+    principal: &str,
+    action: AuditAction,
+    route: &str,
+    instance: &str,
+    status: Status,
+    started: Instant,
+) -> AuditRecordModel {
+    AuditRecordModel {
+        timestamp: humantime::format_rfc3339(SystemTime::now()).to_string(),
+        principal: principal.to_owned(),
+        action,
+        route: route.to_owned(),
+        instance: instance.to_owned(),
+        outcome: if status.class().is_success() {
+            AuditOutcome::Success
+        } else {
+            AuditOutcome::Failure
+        },
+        status: status.code,
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+impl AuditLog {
+    pub fn new(sinks: Vec<AuditSink>, history_size: usize) -> Self {
+        AuditLog {
+            sinks,
+            history_size,
+            history: Mutex::new(VecDeque::with_capacity(history_size)),
+        }
+    }
+
+    /// Send the record to every configured sink and keep it in the in-memory history
+    /// used by `GET /api/audit`. Sink failures are logged but never fail the call.
+    pub async fn record(&self, kubeclient: &Client, record: AuditRecordModel) {
+        let line = serde_json::to_string(&record).unwrap_or_default();
+
+        for sink in self.sinks.iter() {
+            match sink {
+                AuditSink::Stdout => println!("{}", line),
+                AuditSink::File(path) => {
+                    let write_result = match OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .await
+                    {
+                        Ok(mut file) => file.write_all(format!("{}\n", line).as_bytes()).await,
+                        Err(err) => Err(err),
+                    };
+
+                    if let Err(err) = write_result {
+                        warn!("Failed to write audit record to {:?}: {}", path, err);
+                    }
+                }
+                AuditSink::KubernetesEvents => {
+                    let publish_result = publish_instance_event(
+                        kubeclient,
+                        &record.instance,
+                        match record.outcome {
+                            AuditOutcome::Success => EventType::Normal,
+                            AuditOutcome::Failure => EventType::Warning,
+                        },
+                        "Audit",
+                        record.action.as_str(),
+                        Some(format!(
+                            "{} by {} returned {} in {}ms",
+                            record.route, record.principal, record.status, record.duration_ms
+                        )),
+                    )
+                    .await;
+
+                    if let Err(err) = publish_result {
+                        warn!(
+                            "Failed to publish audit event for instance {}: {}",
+                            record.instance, err
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut history = self.history.lock().unwrap();
+
+        if history.len() >= self.history_size {
+            history.pop_front();
+        }
+        history.push_back(record);
+    }
+
+    /// Return the records matching the filter, most recent first.
+    pub fn query(&self, filter: &AuditFilter) -> Vec<AuditRecordModel> {
+        let history = self.history.lock().unwrap();
+
+        history
+            .iter()
+            .rev()
+            .filter(|record| filter.principal.is_none_or(|p| record.principal == p))
+            .filter(|record| filter.action.is_none_or(|a| record.action.as_str() == a))
+            .filter(|record| filter.instance.is_none_or(|i| record.instance == i))
+            .filter(|record| filter.outcome.is_none_or(|o| record.outcome.as_str() == o))
+            .filter(|record| {
+                filter.since.is_none_or(|since| {
+                    humantime::parse_rfc3339(&record.timestamp).is_ok_and(|ts| ts >= since)
+                })
+            })
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use k8s_openapi::api::apps::v1::StatefulSet;
+use kube::{api::ListParams, Api};
+use log::{info, warn};
+use rocket::tokio::time::{interval, MissedTickBehavior};
+
+use crate::activator::switch_ingress;
+use crate::context::Context;
+use crate::kubernetes::{
+    instance_auto_pause, instance_connections, instance_phase, kubernetes_scale_instance,
+    publish_lifecycle_event, InstanceEvent,
+};
+use crate::metrics::metrics;
+use crate::models::database::DatabasePhase;
+
+/// How often the connections of the instances with auto-pause are counted.
+const AUTO_PAUSE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Pause the ready instances that had no connections for longer than their
+/// `moonscale/auto-pause` delay. Idle time is counted from the first check seeing
+/// the instance, so a restart of moonscale never pauses an instance early.
+pub async fn auto_pause_instances(context: Context) {
+    let api: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let mut ticker = interval(AUTO_PAUSE_CHECK_INTERVAL);
+    let mut last_active: HashMap<String, Instant> = HashMap::new();
+
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let started = Instant::now();
+        let managed_sts = api
+            .list(&ListParams::default().labels("app.kubernetes.io/managed-by=Moonscale"))
+            .await;
+
+        metrics().observe_kubernetes_call("list", "StatefulSet", started);
+        let managed_sts = match managed_sts {
+            Ok(managed_sts) => managed_sts.items,
+            Err(err) => {
+                warn!("Failed to list the instances to auto-pause: {}", err);
+                continue;
+            }
+        };
+        let mut watched = HashMap::new();
+
+        for sts in &managed_sts {
+            let Some(name) = sts
+                .metadata
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get("app.kubernetes.io/instance"))
+            else {
+                continue;
+            };
+            let Some(auto_pause) = instance_auto_pause(sts) else {
+                continue;
+            };
+
+            // Instances that aren't running are idle from the moment they are ready
+            if instance_phase(sts) != DatabasePhase::Ready {
+                continue;
+            }
+            let active_at = *last_active.get(name).unwrap_or(&started);
+
+            match instance_connections(&context.kubernetes_client, name).await {
+                Ok(0) => {}
+                Ok(_) => {
+                    watched.insert(name.clone(), started);
+                    continue;
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to count the connections of instance {}: {:#}",
+                        name, err
+                    );
+                    watched.insert(name.clone(), active_at);
+                    continue;
+                }
+            }
+            if active_at.elapsed() < auto_pause {
+                watched.insert(name.clone(), active_at);
+                continue;
+            }
+
+            match kubernetes_scale_instance(&context.kubernetes_client, name, true).await {
+                Ok(_) => {
+                    switch_ingress(&context, name, true).await;
+                    info!(
+                        instance = name.as_str();
+                        "Paused instance {} after {}s without connections",
+                        name,
+                        auto_pause.as_secs()
+                    );
+                    publish_lifecycle_event(
+                        &context.kubernetes_client,
+                        name,
+                        InstanceEvent::Paused,
+                        format!(
+                            "Paused moonscale instance {} after {} without connections",
+                            name,
+                            humantime::format_duration(auto_pause)
+                        ),
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    warn!("Failed to auto-pause instance {}: {}", name, err);
+                    watched.insert(name.clone(), active_at);
+                }
+            }
+        }
+        // Forget the instances that were paused, deleted or lost their auto-pause
+        last_active = watched;
+    }
+}
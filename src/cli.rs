@@ -0,0 +1,141 @@
+use std::fs;
+
+use log::error;
+
+use crate::context::Config;
+use crate::discovery::DiscoveryCache;
+use crate::dry_run::dry_run_database;
+use crate::models::database::{CreateDatabaseRequestModel, ResourcePreset};
+use crate::template::template_api_groups;
+
+const TEMPLATE_USAGE: &str = "Usage: moonscale template check [--name <name>] [--size <size>] [--profile <profile>] [--preset <preset>] [--owner <principal>] [--template <path>] [--manifests]";
+
+/// Options of `moonscale template check`.
+struct TemplateCheckOptions {
+    name: String,
+    size: Option<String>,
+    profile: Option<String>,
+    preset: Option<ResourcePreset>,
+    owner: String,
+    template: Option<String>,
+    manifests: bool,
+}
+
+fn parse_template_check_options(args: &[String]) -> Result<TemplateCheckOptions, String> {
+    let mut options = TemplateCheckOptions {
+        name: "template-check".to_owned(),
+        size: None,
+        profile: None,
+        preset: None,
+        owner: "default".to_owned(),
+        template: None,
+        manifests: false,
+    };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or(format!("Missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--name" => options.name = value()?,
+            "--size" => options.size = Some(value()?),
+            "--profile" => options.profile = Some(value()?),
+            "--preset" => {
+                let preset = value()?;
+
+                options.preset = Some(
+                    ResourcePreset::from_name(&preset)
+                        .ok_or(format!("Unknown preset {}", preset))?,
+                )
+            }
+            "--owner" => options.owner = value()?,
+            "--template" => options.template = Some(value()?),
+            "--manifests" => options.manifests = true,
+            other => return Err(format!("Unknown option {}", other)),
+        }
+    }
+    Ok(options)
+}
+
+/// Render the template and dry run it against the cluster, printing the errors.
+async fn template_check(
+    config: &Config,
+    kubeclient: &kube::Client,
+    template_data: &str,
+    args: &[String],
+) -> Result<(), ()> {
+    let options = parse_template_check_options(args).map_err(|err| {
+        eprintln!("{}\n{}", err, TEMPLATE_USAGE);
+    })?;
+    let template_data = match &options.template {
+        Some(path) => fs::read_to_string(path).map_err(|err| {
+            error!("Failed to read template {}: {}", path, err);
+        })?,
+        None => template_data.to_owned(),
+    };
+    let request = CreateDatabaseRequestModel {
+        name: options.name,
+        size: options.size,
+        profile: options.profile,
+        preset: options.preset,
+        labels: Default::default(),
+        annotations: Default::default(),
+        ttl: None,
+        auto_pause: None,
+    };
+    let result = dry_run_database(
+        config,
+        kubeclient,
+        &DiscoveryCache::new(
+            config.discovery_ttl,
+            template_api_groups(config, &template_data),
+        ),
+        &template_data,
+        &request,
+        &options.owner,
+    )
+    .await;
+
+    if options.manifests {
+        for manifest in &result.manifests {
+            match serde_yaml::to_string(manifest) {
+                Ok(manifest) => println!("---\n{}", manifest),
+                Err(err) => eprintln!("Failed to print manifest: {}", err),
+            }
+        }
+    }
+    for err in &result.errors {
+        match (&err.kind, &err.name) {
+            (Some(kind), Some(name)) => println!("error: {}/{}: {}", kind, name, err.message),
+            _ => println!("error: {}", err.message),
+        }
+    }
+    if !result.valid {
+        return Err(());
+    }
+    println!(
+        "Template is valid, {} manifests would be applied",
+        result.manifests.len()
+    );
+    Ok(())
+}
+
+/// Entry point of the `moonscale template` subcommands.
+pub async fn template_command(
+    config: &Config,
+    kubeclient: &kube::Client,
+    template_data: &str,
+    args: &[String],
+) -> Result<(), ()> {
+    match args.first().map(String::as_str) {
+        Some("check") => template_check(config, kubeclient, template_data, &args[1..]).await,
+        _ => {
+            eprintln!("{}", TEMPLATE_USAGE);
+            Err(())
+        }
+    }
+}
@@ -1,11 +1,70 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use crate::activator::{Activator, ActivatorConfig};
+use crate::audit::{AuditLog, AuditSink};
+use crate::discovery::DiscoveryCache;
+use crate::health::HealthChecker;
+use crate::lifecycle::LifecycleHub;
+use crate::operations::OperationStore;
+use crate::profiles::Profile;
+use crate::proxy::{PasswordCache, ProxyConfig};
+use crate::quota::QuotaLimits;
+use crate::webhooks::WebhookStore;
+
+#[derive(Clone)]
 pub struct Config {
+    /// Namespace in which every instance is deployed.
+    pub namespace: String,
     pub ingress_domain: String,
     pub resource_ttl: usize,
-    pub api_key: String,
+    /// API keys accepted by the server, mapped to the name of their principal.
+    pub api_keys: HashMap<String, String>,
+    /// Principals allowed to see and manage every instance, not only the ones they own.
+    pub admin_principals: Vec<String>,
+    /// Secret used by GitHub to sign the pull request webhooks, the integration is
+    /// disabled when unset.
+    pub github_webhook_secret: Option<String>,
+    /// Token sent by GitLab with the merge request webhooks, the integration is
+    /// disabled when unset.
+    pub gitlab_webhook_token: Option<String>,
+    pub audit_sinks: Vec<AuditSink>,
+    pub audit_history_size: usize,
+    pub health_cache_ttl: Duration,
+    /// How long the Kubernetes API discovery is cached before being refreshed.
+    pub discovery_ttl: Duration,
+    /// How long finished operations are kept before being pruned.
+    pub operation_retention: Duration,
+    /// How long before the expiry of an instance an `expiring-soon` event is emitted.
+    pub expiry_warning: Duration,
+    pub quota: QuotaLimits,
+    /// Minutes without connections after which an instance is paused, unless the
+    /// create request sets its own delay.
+    pub auto_pause: Option<usize>,
+    /// Where the Ingress of a paused instance points so the first request wakes it
+    /// up, instances stay unreachable while paused when unset.
+    pub activator: Option<ActivatorConfig>,
+    /// Serve the PlanetScale HTTP API of the instances through moonscale instead of
+    /// an Ingress per instance.
+    pub proxy: Option<ProxyConfig>,
+    /// Storage settings of the instances, keyed by profile name.
+    pub profiles: HashMap<String, Profile>,
 }
 
+/// Shared by every request, cheap to clone so background tasks can own a copy.
+#[derive(Clone)]
 pub struct Context {
     pub database_template_yaml_raw: String,
     pub kubernetes_client: kube::Client,
     pub config: Config,
+    pub audit: Arc<AuditLog>,
+    pub health: Arc<HealthChecker>,
+    pub discovery: Arc<DiscoveryCache>,
+    pub operations: Arc<OperationStore>,
+    pub lifecycle: Arc<LifecycleHub>,
+    pub webhooks: Arc<WebhookStore>,
+    pub activator: Arc<Activator>,
+    pub proxy_passwords: Arc<PasswordCache>,
+    /// Held from the quota check until the instance is created, so concurrent creates
+    /// can't both fit in the last slot.
+    pub quota_lock: Arc<rocket::tokio::sync::Mutex<()>>,
 }
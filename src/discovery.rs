@@ -0,0 +1,108 @@
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use kube::{
+    api::{ApiResource, GroupVersionKind},
+    discovery::{ApiCapabilities, ApiGroup},
+    Client, Discovery,
+};
+use log::info;
+use rocket::tokio::sync::RwLock;
+
+use crate::telemetry::in_span;
+
+/// Minimum delay between two refreshes triggered by unknown kinds, so a template
+/// referencing a kind that doesn't exist can't make us hammer the API server.
+const MISS_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// API discovery shared between requests, refreshed when older than the TTL or
+/// when a kind we are asked to resolve is unknown. Only the API groups used by the
+/// template are discovered, along with the groups of the kinds resolved since.
+pub struct DiscoveryCache {
+    ttl: Duration,
+    groups: Mutex<BTreeSet<String>>,
+    cache: RwLock<Option<(Instant, Arc<Discovery>)>>,
+}
+
+impl DiscoveryCache {
+    pub fn new(ttl: Duration, groups: impl IntoIterator<Item = String>) -> Self {
+        let mut groups: BTreeSet<String> = groups.into_iter().collect();
+
+        // PersistentVolumeClaims are resolved without being in the template
+        groups.insert(ApiGroup::CORE_GROUP.to_owned());
+        DiscoveryCache {
+            ttl,
+            groups: Mutex::new(groups),
+            cache: RwLock::new(None),
+        }
+    }
+
+    /// Run a discovery unless another caller refreshed the cache since `stale_before`.
+    async fn refresh(
+        &self,
+        kubeclient: &Client,
+        stale_before: Instant,
+    ) -> Result<Arc<Discovery>, kube::Error> {
+        let mut cache = self.cache.write().await;
+
+        if let Some((refreshed_at, discovery)) = cache.as_ref() {
+            if *refreshed_at > stale_before {
+                return Ok(discovery.clone());
+            }
+        }
+
+        let groups: Vec<String> = self.groups.lock().unwrap().iter().cloned().collect();
+        let groups: Vec<&str> = groups.iter().map(String::as_str).collect();
+
+        info!("Refreshing Kubernetes API discovery of groups {:?}", groups);
+        let discovery = Arc::new(
+            in_span(
+                "kubernetes.discovery",
+                vec![],
+                Discovery::new(kubeclient.clone()).filter(&groups).run(),
+            )
+            .await?,
+        );
+
+        *cache = Some((Instant::now(), discovery.clone()));
+        Ok(discovery)
+    }
+
+    pub async fn get(&self, kubeclient: &Client) -> Result<Arc<Discovery>, kube::Error> {
+        let now = Instant::now();
+
+        if let Some((refreshed_at, discovery)) = self.cache.read().await.as_ref() {
+            if now.duration_since(*refreshed_at) < self.ttl {
+                return Ok(discovery.clone());
+            }
+        }
+        self.refresh(kubeclient, now.checked_sub(self.ttl).unwrap_or(now))
+            .await
+    }
+
+    /// Resolve a kind, refreshing the discovery once if it's unknown as it may have
+    /// been installed since the last refresh (eg: a new CRD), or its group wasn't
+    /// discovered yet.
+    pub async fn resolve_gvk(
+        &self,
+        kubeclient: &Client,
+        gvk: &GroupVersionKind,
+    ) -> Result<Option<(ApiResource, ApiCapabilities)>, kube::Error> {
+        if let Some(resolved) = self.get(kubeclient).await?.resolve_gvk(gvk) {
+            return Ok(Some(resolved));
+        }
+
+        let now = Instant::now();
+        let new_group = self.groups.lock().unwrap().insert(gvk.group.clone());
+        let stale_before = match new_group {
+            true => now,
+            false => now.checked_sub(MISS_REFRESH_INTERVAL).unwrap_or(now),
+        };
+        let discovery = self.refresh(kubeclient, stale_before).await?;
+
+        Ok(discovery.resolve_gvk(gvk))
+    }
+}
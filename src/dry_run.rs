@@ -0,0 +1,143 @@
+use k8s_openapi::serde_json;
+use kube::{
+    api::{DynamicObject, PatchParams},
+    Client, ResourceExt,
+};
+use rand::distributions::{Alphanumeric, DistString};
+
+use crate::context::Config;
+use crate::discovery::DiscoveryCache;
+use crate::kubernetes::kubernetes_dry_run_document;
+use crate::metadata::InstanceMetadata;
+use crate::models::database::{
+    CreateDatabaseRequestModel, DryRunDatabaseResponseModel, DryRunErrorModel,
+};
+use crate::profiles::resolve_profile;
+use crate::quantity::format_quantity;
+use crate::template::{
+    ensure_instance_scoped, instance_auto_pause, instance_template_context, instance_ttl,
+    multidoc_deserialize, validate_instance_name,
+};
+
+fn template_error(message: String) -> DryRunErrorModel {
+    DryRunErrorModel {
+        kind: None,
+        name: None,
+        message,
+    }
+}
+
+/// Render the template for the request and server-side apply every manifest with
+/// `dryRun=All`, collecting every error instead of stopping at the first one.
+pub async fn dry_run_database(
+    config: &Config,
+    kubeclient: &Client,
+    api_discovery: &DiscoveryCache,
+    template_data: &str,
+    request: &CreateDatabaseRequestModel,
+    owner: &str,
+) -> DryRunDatabaseResponseModel {
+    let mut manifests = vec![];
+    let mut errors = vec![];
+
+    // The name is rendered in the YAML, nothing else can be checked without a valid one
+    if let Err(err) = validate_instance_name(config, template_data, &request.name) {
+        return DryRunDatabaseResponseModel {
+            valid: false,
+            manifests,
+            errors: vec![template_error(err)],
+        };
+    }
+    let profile = match resolve_profile(config, request.profile.as_deref()) {
+        Ok(profile) => profile,
+        Err(err) => {
+            return DryRunDatabaseResponseModel {
+                valid: false,
+                manifests,
+                errors: vec![template_error(err)],
+            }
+        }
+    };
+    let size = profile
+        .resolve_size(request.size.as_deref())
+        .unwrap_or_else(|err| {
+            errors.push(template_error(err));
+            profile.default_size
+        });
+    let preset = request.preset.unwrap_or(profile.default_preset);
+    let metadata = match InstanceMetadata::from_user(&request.labels, &request.annotations) {
+        Ok(metadata) => metadata
+            .with_system_metadata(
+                &request.name,
+                owner,
+                &profile.name,
+                preset,
+                instance_ttl(config, request.ttl),
+            )
+            .with_auto_pause(instance_auto_pause(config, request.auto_pause)),
+        Err(err) => {
+            errors.push(template_error(err));
+            InstanceMetadata::default()
+        }
+    };
+    let mut template_context = instance_template_context(
+        config,
+        &request.name,
+        owner,
+        &Alphanumeric.sample_string(&mut rand::thread_rng(), 16),
+        profile,
+        &format_quantity(size),
+        preset,
+    );
+    let docs = match multidoc_deserialize(template_data, &mut template_context) {
+        Ok(docs) => docs,
+        Err(err) => {
+            errors.push(template_error(format!("{:#}", err)));
+            vec![]
+        }
+    };
+    let patch_params = PatchParams::apply("kubectl-light").force();
+
+    for mut doc in docs {
+        let kind = doc["kind"].as_str().map(str::to_owned);
+        let name = doc["metadata"]["name"].as_str().map(str::to_owned);
+        let error = |message: String| DryRunErrorModel {
+            kind: kind.clone(),
+            name: name.clone(),
+            message,
+        };
+
+        if let Err(err) = ensure_instance_scoped(&request.name, &doc) {
+            errors.push(error(err.to_string()));
+        }
+        metadata.inject(&mut doc);
+        match serde_json::to_value(&doc) {
+            Ok(manifest) => manifests.push(manifest),
+            Err(err) => errors.push(error(format!("Failed to convert manifest: {}", err))),
+        }
+
+        let obj: DynamicObject = match serde_yaml::from_value(doc) {
+            Ok(obj) => obj,
+            Err(err) => {
+                errors.push(error(format!("Invalid manifest: {}", err)));
+                continue;
+            }
+        };
+
+        if let Err(err) =
+            kubernetes_dry_run_document(kubeclient, api_discovery, &patch_params, &obj).await
+        {
+            errors.push(error(format!(
+                "Dry run of {} failed: {:#}",
+                obj.name_any(),
+                err
+            )));
+        }
+    }
+
+    DryRunDatabaseResponseModel {
+        valid: errors.is_empty(),
+        manifests,
+        errors,
+    }
+}
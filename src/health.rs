@@ -0,0 +1,161 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use k8s_openapi::api::{apps::v1::StatefulSet, core::v1::Secret};
+use kube::{api::ListParams, Api};
+use log::warn;
+use rocket::tokio::{self, time::timeout};
+
+use crate::{
+    context::Context,
+    models::health::{HealthCheckModel, HealthReportModel},
+    profiles::DEFAULT_PROFILE,
+    template::{ensure_instance_scoped, instance_template_context, multidoc_deserialize},
+};
+
+/// How long a check may take before it's reported as failed, below the default
+/// timeout of the kubelet probes.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Runs the readiness checks, caching the report so frequent probes don't hammer
+/// the Kubernetes API.
+pub struct HealthChecker {
+    cache_ttl: Duration,
+    cache: Mutex<Option<(Instant, HealthReportModel)>>,
+    /// Held while the checks run, so concurrent probes wait for a single refresh.
+    refresh_lock: tokio::sync::Mutex<()>,
+}
+
+fn check_result(name: &str, started: Instant, result: Result<(), String>) -> HealthCheckModel {
+    if let Err(message) = &result {
+        warn!("Health check {} failed: {}", name, message);
+    }
+    HealthCheckModel {
+        name: name.to_owned(),
+        healthy: result.is_ok(),
+        message: result.err(),
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+async fn check_kubernetes_api(context: &Context) -> HealthCheckModel {
+    let started = Instant::now();
+    let result = match timeout(
+        HEALTH_CHECK_TIMEOUT,
+        context.kubernetes_client.apiserver_version(),
+    )
+    .await
+    {
+        Ok(version) => version
+            .map(|_| ())
+            .map_err(|err| format!("Kubernetes API is unreachable: {}", err)),
+        Err(_) => Err(format!(
+            "Kubernetes API didn't answer within {}s",
+            HEALTH_CHECK_TIMEOUT.as_secs()
+        )),
+    };
+
+    check_result("kubernetes-api", started, result)
+}
+
+async fn check_namespace_access(context: &Context) -> HealthCheckModel {
+    let started = Instant::now();
+    let namespace = context.kubernetes_client.default_namespace().to_owned();
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let api_secrets: Api<Secret> = Api::default_namespaced(context.kubernetes_client.clone());
+    let list_params = ListParams::default().limit(1);
+    let checks = async {
+        match api_sts.list_metadata(&list_params).await {
+            Err(err) => Err(format!(
+                "Cannot list StatefulSets in namespace {}: {}",
+                namespace, err
+            )),
+            Ok(_) => api_secrets
+                .list_metadata(&list_params)
+                .await
+                .map(|_| ())
+                .map_err(|err| format!("Cannot list Secrets in namespace {}: {}", namespace, err)),
+        }
+    };
+    let result = timeout(HEALTH_CHECK_TIMEOUT, checks)
+        .await
+        .unwrap_or_else(|_| {
+            Err(format!(
+                "Listing namespace {} didn't complete within {}s",
+                namespace,
+                HEALTH_CHECK_TIMEOUT.as_secs()
+            ))
+        });
+
+    check_result("namespace-access", started, result)
+}
+
+fn check_template(context: &Context) -> HealthCheckModel {
+    let started = Instant::now();
+    let profile = &context.config.profiles[DEFAULT_PROFILE];
+    let mut template_context = instance_template_context(
+        &context.config,
+        "healthcheck",
+        "healthcheck",
+        "healthcheck",
+        profile,
+        "1Gi",
+        profile.default_preset,
+    );
+    let result = multidoc_deserialize(&context.database_template_yaml_raw, &mut template_context)
+        .and_then(|docs| {
+            docs.iter()
+                .try_for_each(|doc| ensure_instance_scoped("healthcheck", doc))
+        })
+        .map_err(|err| format!("Database template is invalid: {:#}", err));
+
+    check_result("template", started, result)
+}
+
+impl HealthChecker {
+    pub fn new(cache_ttl: Duration) -> Self {
+        HealthChecker {
+            cache_ttl,
+            cache: Mutex::new(None),
+            refresh_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn cached_report(&self) -> Option<HealthReportModel> {
+        match self.cache.lock().unwrap().as_ref() {
+            Some((checked_at, report)) if checked_at.elapsed() < self.cache_ttl => {
+                Some(report.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the cached report if it's still fresh, or run every check again.
+    pub async fn report(&self, context: &Context) -> HealthReportModel {
+        if let Some(report) = self.cached_report() {
+            return report;
+        }
+        let _refresh_guard = self.refresh_lock.lock().await;
+
+        // Another probe may have refreshed the report while we were waiting
+        if let Some(report) = self.cached_report() {
+            return report;
+        }
+
+        let checks = vec![
+            check_kubernetes_api(context).await,
+            check_namespace_access(context).await,
+            check_template(context),
+        ];
+        let report = HealthReportModel {
+            healthy: checks.iter().all(|check| check.healthy),
+            checked_at: humantime::format_rfc3339(SystemTime::now()).to_string(),
+            checks: Some(checks),
+        };
+
+        *self.cache.lock().unwrap() = Some((Instant::now(), report.clone()));
+        report
+    }
+}
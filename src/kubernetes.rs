@@ -1,14 +1,58 @@
+use std::time::{Duration, Instant, SystemTime};
+
 use anyhow::Context;
-use k8s_openapi::{api::core::v1::Secret, serde_json};
+use k8s_openapi::{
+    api::{
+        apps::v1::StatefulSet,
+        core::v1::{ObjectReference, PersistentVolumeClaim, Pod, Secret},
+        storage::v1::StorageClass,
+    },
+    serde_json,
+};
 use kube::{
-    api::{ApiResource, DynamicObject, GroupVersionKind, Patch},
+    api::{
+        ApiResource, AttachParams, DeleteParams, DynamicObject, GroupVersionKind, Patch,
+        PatchParams,
+    },
     discovery::{ApiCapabilities, Scope},
-    Api, Client, Discovery, ResourceExt,
+    runtime::{
+        events::{Event, EventType, Recorder, Reporter},
+        wait::await_condition,
+    },
+    Api, Client, ResourceExt,
 };
-use log::error;
 use log::info;
+use log::{error, warn};
+use opentelemetry::KeyValue;
+use rocket::tokio::io::AsyncReadExt;
 use serde_yaml::Value;
 
+use crate::{
+    discovery::DiscoveryCache,
+    metadata::InstanceMetadata,
+    metrics::metrics,
+    models::database::DatabasePhase,
+    telemetry::in_span,
+    template::{ensure_instance_scoped, InstanceResource},
+};
+
+/// Annotation of the StatefulSet recording the size the volumes were resized to.
+pub const INSTANCE_SIZE_ANNOTATION: &str = "moonscale/size";
+/// Annotation of the StatefulSet recording when the instance was paused.
+pub const INSTANCE_PAUSED_AT_ANNOTATION: &str = "moonscale/paused-at";
+/// Annotation of the StatefulSet holding how long the instance may stay idle before
+/// being paused, eg: `30m`.
+pub const INSTANCE_AUTO_PAUSE_ANNOTATION: &str = "moonscale/auto-pause";
+/// Replicas of a running instance, the template deploys a single MySQL server.
+const INSTANCE_REPLICAS: i32 = 1;
+/// Connections of the instance, leaving out our own, the MySQL system threads and
+/// the pooled connections that stayed idle for more than a minute.
+const INSTANCE_CONNECTIONS_QUERY: &str = "SELECT COUNT(*) FROM information_schema.PROCESSLIST \
+WHERE ID != CONNECTION_ID() AND USER NOT IN ('event_scheduler', 'system user') \
+AND (COMMAND != 'Sleep' OR TIME < 60)";
+/// How long we wait for a freshly created instance to become ready.
+pub const INSTANCE_READY_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
 fn dynamic_api(
     ar: ApiResource,
     caps: ApiCapabilities,
@@ -25,36 +69,396 @@ fn dynamic_api(
     }
 }
 
+/// Reference to the StatefulSet backing a moonscale instance, used as the
+/// regarding object of the Kubernetes Events we publish for this instance.
+fn instance_reference(kubeclient: &Client, instance_name: &str) -> ObjectReference {
+    ObjectReference {
+        api_version: Some("apps/v1".to_owned()),
+        kind: Some("StatefulSet".to_owned()),
+        name: Some(format!("moonscale-instance-{}", instance_name)),
+        namespace: Some(kubeclient.default_namespace().to_owned()),
+        ..Default::default()
+    }
+}
+
+pub async fn publish_instance_event(
+    kubeclient: &Client,
+    instance_name: &str,
+    type_: EventType,
+    reason: &str,
+    action: &str,
+    note: Option<String>,
+) -> Result<(), kube::Error> {
+    let reporter = Reporter {
+        controller: "moonscale".to_owned(),
+        instance: None,
+    };
+    let recorder = Recorder::new(
+        kubeclient.clone(),
+        reporter,
+        instance_reference(kubeclient, instance_name),
+    );
+
+    recorder
+        .publish(Event {
+            type_,
+            reason: reason.to_owned(),
+            note,
+            action: action.to_owned(),
+            secondary: None,
+        })
+        .await
+}
+
+/// Lifecycle steps of a moonscale instance published as Kubernetes Events, so
+/// `kubectl describe` shows the history of each preview database.
+pub enum InstanceEvent {
+    Created,
+    CreateFailed,
+    Applied,
+    ApplyFailed,
+    Deleted,
+    ReadyTimeout,
+    ExpiringSoon,
+    Expired,
+    Paused,
+    Resumed,
+}
+
+impl InstanceEvent {
+    fn reason(&self) -> &'static str {
+        match self {
+            InstanceEvent::Created => "Created",
+            InstanceEvent::CreateFailed => "CreateFailed",
+            InstanceEvent::Applied => "Applied",
+            InstanceEvent::ApplyFailed => "ApplyFailed",
+            InstanceEvent::Deleted => "Deleted",
+            InstanceEvent::ReadyTimeout => "ReadyTimeout",
+            InstanceEvent::ExpiringSoon => "ExpiringSoon",
+            InstanceEvent::Expired => "Expired",
+            InstanceEvent::Paused => "Paused",
+            InstanceEvent::Resumed => "Resumed",
+        }
+    }
+
+    fn action(&self) -> &'static str {
+        match self {
+            InstanceEvent::Created | InstanceEvent::CreateFailed => "Create",
+            InstanceEvent::Applied | InstanceEvent::ApplyFailed => "Apply",
+            InstanceEvent::Deleted => "Delete",
+            InstanceEvent::ReadyTimeout => "Provision",
+            InstanceEvent::ExpiringSoon | InstanceEvent::Expired => "Expire",
+            InstanceEvent::Paused => "Pause",
+            InstanceEvent::Resumed => "Resume",
+        }
+    }
+
+    fn event_type(&self) -> EventType {
+        match self {
+            InstanceEvent::CreateFailed
+            | InstanceEvent::ApplyFailed
+            | InstanceEvent::ReadyTimeout
+            | InstanceEvent::ExpiringSoon => EventType::Warning,
+            _ => EventType::Normal,
+        }
+    }
+}
+
+/// Publish a lifecycle event for the instance, failures are only logged as events
+/// are informative and should never fail the underlying operation.
+pub async fn publish_lifecycle_event(
+    kubeclient: &Client,
+    instance_name: &str,
+    event: InstanceEvent,
+    note: String,
+) {
+    let publish_result = publish_instance_event(
+        kubeclient,
+        instance_name,
+        event.event_type(),
+        event.reason(),
+        event.action(),
+        Some(note),
+    )
+    .await;
+
+    if let Err(err) = publish_result {
+        warn!(
+            "Failed to publish {} event for instance {}: {}",
+            event.reason(),
+            instance_name,
+            err
+        );
+    }
+}
+
+fn is_statefulset_ready(sts: &StatefulSet) -> bool {
+    let replicas = sts
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.replicas)
+        .unwrap_or(1);
+    let ready_replicas = sts
+        .status
+        .as_ref()
+        .and_then(|status| status.ready_replicas)
+        .unwrap_or(0);
+
+    replicas > 0 && ready_replicas >= replicas
+}
+
+fn is_statefulset_paused(sts: &StatefulSet) -> bool {
+    sts.spec.as_ref().and_then(|spec| spec.replicas) == Some(0)
+}
+
+pub fn instance_phase(sts: &StatefulSet) -> DatabasePhase {
+    if sts.metadata.deletion_timestamp.is_some() {
+        DatabasePhase::Terminating
+    } else if is_statefulset_paused(sts) {
+        DatabasePhase::Paused
+    } else if is_statefulset_ready(sts) {
+        DatabasePhase::Ready
+    } else {
+        DatabasePhase::Provisioning
+    }
+}
+
+/// The principal owning the instance, from the owner label of its StatefulSet.
+pub fn instance_owner(sts: &StatefulSet) -> Option<String> {
+    sts.metadata
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get("moonscale/owner"))
+        .filter(|owner| !owner.is_empty())
+        .cloned()
+}
+
+pub fn instance_created_at(sts: &StatefulSet) -> Option<SystemTime> {
+    sts.metadata
+        .creation_timestamp
+        .as_ref()
+        .map(|timestamp| SystemTime::from(timestamp.0))
+}
+
+/// When the janitor will delete the instance, from the `janitor/ttl` annotation.
+pub fn instance_expires_at(sts: &StatefulSet) -> Option<SystemTime> {
+    let ttl = sts
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get("janitor/ttl"))
+        .and_then(|ttl| humantime::parse_duration(ttl).ok())?;
+
+    instance_created_at(sts).map(|created_at| created_at + ttl)
+}
+
+/// When the instance was paused, from the `moonscale/paused-at` annotation.
+pub fn instance_paused_at(sts: &StatefulSet) -> Option<SystemTime> {
+    sts.metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(INSTANCE_PAUSED_AT_ANNOTATION))
+        .and_then(|paused_at| humantime::parse_rfc3339(paused_at).ok())
+}
+
+/// How long the instance may stay without connections before being paused.
+pub fn instance_auto_pause(sts: &StatefulSet) -> Option<Duration> {
+    sts.metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(INSTANCE_AUTO_PAUSE_ANNOTATION))
+        .and_then(|auto_pause| humantime::parse_duration(auto_pause).ok())
+        .filter(|auto_pause| !auto_pause.is_zero())
+}
+
+/// The storage of the instance: the size it was last resized to, recorded in the
+/// `moonscale/size` annotation as claim templates are immutable, or the storage
+/// requested by the first volume claim template of the StatefulSet.
+pub fn instance_size(sts: &StatefulSet) -> Option<String> {
+    if let Some(size) = sts
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(INSTANCE_SIZE_ANNOTATION))
+    {
+        return Some(size.clone());
+    }
+    sts.spec
+        .as_ref()?
+        .volume_claim_templates
+        .as_ref()?
+        .first()?
+        .spec
+        .as_ref()?
+        .resources
+        .as_ref()?
+        .requests
+        .as_ref()?
+        .get("storage")
+        .map(|quantity| quantity.0.clone())
+}
+
+/// Wait until the StatefulSet of the instance is ready, returns false if it didn't
+/// become ready before the timeout.
+pub async fn wait_for_instance_ready(
+    kubeclient: &Client,
+    instance_name: &str,
+) -> Result<bool, anyhow::Error> {
+    let sts_api: Api<StatefulSet> = Api::default_namespaced(kubeclient.clone());
+    let sts_name = format!("moonscale-instance-{}", instance_name);
+    let ready = await_condition(sts_api, &sts_name, |sts: Option<&StatefulSet>| {
+        sts.is_some_and(is_statefulset_ready)
+    });
+
+    match rocket::tokio::time::timeout(INSTANCE_READY_TIMEOUT, ready).await {
+        Ok(result) => result.map(|_| true).map_err(|err| err.into()),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Wait until every pod of the paused instance is gone, returns false if some are
+/// still running after the timeout.
+pub async fn wait_for_instance_stopped(
+    kubeclient: &Client,
+    instance_name: &str,
+    timeout: Duration,
+) -> Result<bool, anyhow::Error> {
+    let sts_api: Api<StatefulSet> = Api::default_namespaced(kubeclient.clone());
+    let sts_name = format!("moonscale-instance-{}", instance_name);
+    let stopped = await_condition(sts_api, &sts_name, |sts: Option<&StatefulSet>| {
+        sts.is_none_or(|sts| sts.status.as_ref().map_or(0, |status| status.replicas) == 0)
+    });
+
+    match rocket::tokio::time::timeout(timeout, stopped).await {
+        Ok(result) => result.map(|_| true).map_err(|err| err.into()),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Scale the instance down to zero while keeping its volumes and Secret, or back up
+/// when resuming it. The pause time is recorded so it shows in the status.
+pub async fn kubernetes_scale_instance(
+    kubeclient: &Client,
+    instance_name: &str,
+    paused: bool,
+) -> Result<StatefulSet, kube::Error> {
+    let api: Api<StatefulSet> = Api::default_namespaced(kubeclient.clone());
+    let patch = match paused {
+        true => serde_json::json!({
+            "metadata": { "annotations": {
+                INSTANCE_PAUSED_AT_ANNOTATION:
+                    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+            } },
+            "spec": { "replicas": 0 }
+        }),
+        false => serde_json::json!({
+            "metadata": { "annotations": { INSTANCE_PAUSED_AT_ANNOTATION: null } },
+            "spec": { "replicas": INSTANCE_REPLICAS }
+        }),
+    };
+    let started = Instant::now();
+    let result = api
+        .patch(
+            &format!("moonscale-instance-{}", instance_name),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await;
+
+    metrics().observe_kubernetes_call("patch", "StatefulSet", started);
+    result
+}
+
+/// Count the client connections of the instance by querying MySQL from its pod.
+pub async fn instance_connections(
+    kubeclient: &Client,
+    instance_name: &str,
+) -> Result<usize, anyhow::Error> {
+    let api: Api<Pod> = Api::default_namespaced(kubeclient.clone());
+    let command = format!(
+        "MYSQL_PWD=\"$MYSQL_ROOT_PASSWORD\" mysql -uroot -N -s -e \"{}\"",
+        INSTANCE_CONNECTIONS_QUERY
+    );
+    let started = Instant::now();
+    let attached = api
+        .exec(
+            &format!("moonscale-instance-{}-0", instance_name),
+            ["/bin/bash", "-c", command.as_str()],
+            &AttachParams::default().container("mysql").stderr(false),
+        )
+        .await;
+
+    metrics().observe_kubernetes_call("exec", "Pod", started);
+    let mut attached = attached?;
+    let mut output = String::new();
+
+    attached
+        .stdout()
+        .context("The exec session has no stdout")?
+        .read_to_string(&mut output)
+        .await?;
+    attached.join().await?;
+    output
+        .trim()
+        .parse()
+        .with_context(|| format!("Unexpected connection count {:?}", output.trim()))
+}
+
+/// Whether the StatefulSet backing the instance exists.
+pub async fn instance_exists(
+    kubeclient: &Client,
+    instance_name: &str,
+) -> Result<bool, kube::Error> {
+    let sts_api: Api<StatefulSet> = Api::default_namespaced(kubeclient.clone());
+    let started = Instant::now();
+    let sts = sts_api
+        .get_opt(&format!("moonscale-instance-{}", instance_name))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "StatefulSet", started);
+    Ok(sts?.is_some())
+}
+
 pub async fn get_database_password(kubeclient: &Client, instance_name: &str) -> Result<String, ()> {
-    let sec_api: Api<Secret> = kube::Api::namespaced(kubeclient.clone(), "moonscale");
+    let sec_api: Api<Secret> = kube::Api::default_namespaced(kubeclient.clone());
+    let started = Instant::now();
     let database_sec = sec_api
         .get(format!("moonscale-instance-{}", instance_name).as_str())
         .await;
 
+    metrics().observe_kubernetes_call("get", "Secret", started);
+
     if database_sec.is_err() {
         error!("Failed to get secret for database {}", instance_name);
         return Err(());
     }
 
-    let database_secret_kv = database_sec.unwrap().data.unwrap();
-    let root_password_kv = database_secret_kv.get("mysql-root-password");
+    let root_password = database_sec
+        .unwrap()
+        .data
+        .and_then(|mut data| data.remove("mysql-root-password"))
+        .and_then(|password| String::from_utf8(password.0).ok());
 
-    if root_password_kv.is_none() {
+    if root_password.is_none() {
         error!("Failed to get root password for database {}", instance_name);
         return Err(());
     }
 
-    Ok(String::from_utf8(root_password_kv.unwrap().0.clone()).unwrap())
+    Ok(root_password.unwrap())
 }
 
 pub async fn kubernetes_apply_document(
     kubeclient: &Client,
-    api_discovery: &Discovery,
+    instance_name: &str,
+    api_discovery: &DiscoveryCache,
     patch_params: &kube::api::PatchParams,
-    doc: Value,
+    metadata: &InstanceMetadata,
+    mut doc: Value,
 ) -> Result<(), anyhow::Error> {
+    ensure_instance_scoped(instance_name, &doc)?;
+    metadata.inject(&mut doc);
     let obj: DynamicObject = serde_yaml::from_value(doc)?;
-    let namespace = obj.metadata.namespace.as_deref().or(Some("moonscale"));
+    let namespace = obj.metadata.namespace.as_deref();
     let type_meta = obj.types.as_ref();
 
     if type_meta.is_none() {
@@ -64,27 +468,236 @@ pub async fn kubernetes_apply_document(
     let gvk = GroupVersionKind::try_from(type_meta.unwrap()).context("Failed to get GVK")?;
     let name = obj.name_any();
 
-    if let Some((ar, caps)) = api_discovery.resolve_gvk(&gvk) {
+    if let Some((ar, caps)) = api_discovery.resolve_gvk(kubeclient, &gvk).await? {
         let api = dynamic_api(ar, caps, kubeclient.clone(), namespace, false);
         let data: serde_json::Value =
             serde_json::to_value(&obj).context("Failed to serialize object to JSON")?;
-        let api_patch_result = api.patch(&name, patch_params, &Patch::Apply(data)).await;
+        let started = Instant::now();
+        let api_patch_result = in_span(
+            "kubernetes.apply",
+            vec![
+                KeyValue::new("k8s.kind", gvk.kind.clone()),
+                KeyValue::new("k8s.name", name.clone()),
+            ],
+            api.patch(&name, patch_params, &Patch::Apply(data)),
+        )
+        .await;
 
-        // TODO: Add system labels injection (managed-by...)
+        metrics().observe_kubernetes_call("patch", &gvk.kind, started);
         if api_patch_result.is_err() {
+            metrics().apply_errors.with_label_values(&[&gvk.kind]).inc();
             error!(
+                instance = instance_name;
                 "Failed to apply document for {:?}: {:?}",
                 name, api_patch_result
             );
-            return Err(api_patch_result.err().unwrap().into());
+            let err = api_patch_result.err().unwrap();
+
+            publish_lifecycle_event(
+                kubeclient,
+                instance_name,
+                InstanceEvent::ApplyFailed,
+                format!("Failed to apply {}/{}: {}", gvk.kind, name, err),
+            )
+            .await;
+            return Err(err.into());
         }
-        info!("Applied {:?}", &obj.types.unwrap());
+        info!(instance = instance_name; "Applied {:?}", &obj.types.unwrap());
+        publish_lifecycle_event(
+            kubeclient,
+            instance_name,
+            InstanceEvent::Applied,
+            format!("Applied {}/{}", gvk.kind, name),
+        )
+        .await;
     } else {
         error!(
+            instance = instance_name;
             "Cannot apply document for unknown type {:?}",
             &obj.types.unwrap()
         );
+        metrics().apply_errors.with_label_values(&[&gvk.kind]).inc();
+        publish_lifecycle_event(
+            kubeclient,
+            instance_name,
+            InstanceEvent::ApplyFailed,
+            format!("Cannot apply {}/{}: unknown type", gvk.kind, name),
+        )
+        .await;
         return Err(anyhow::anyhow!("Unknown type"));
     }
     Ok(())
 }
+
+/// Server-side apply the object with `dryRun=All`, returning it as the API server
+/// would have persisted it. Nothing is created or published.
+pub async fn kubernetes_dry_run_document(
+    kubeclient: &Client,
+    api_discovery: &DiscoveryCache,
+    patch_params: &kube::api::PatchParams,
+    obj: &DynamicObject,
+) -> Result<DynamicObject, anyhow::Error> {
+    let type_meta = obj
+        .types
+        .as_ref()
+        .context("Document has no type metadata")?;
+    let gvk = GroupVersionKind::try_from(type_meta).context("Failed to get GVK")?;
+    let name = obj.name_any();
+    let Some((ar, caps)) = api_discovery.resolve_gvk(kubeclient, &gvk).await? else {
+        return Err(anyhow::anyhow!(
+            "Unknown type {}/{} {}",
+            gvk.group,
+            gvk.version,
+            gvk.kind
+        ));
+    };
+    let api = dynamic_api(
+        ar,
+        caps,
+        kubeclient.clone(),
+        obj.metadata.namespace.as_deref(),
+        false,
+    );
+    let data: serde_json::Value =
+        serde_json::to_value(obj).context("Failed to serialize object to JSON")?;
+    let mut patch_params = patch_params.clone();
+    let started = Instant::now();
+
+    patch_params.dry_run = true;
+    let result = api.patch(&name, &patch_params, &Patch::Apply(data)).await;
+
+    metrics().observe_kubernetes_call("patch", &gvk.kind, started);
+    Ok(result?)
+}
+
+pub enum DeleteOutcome {
+    Deleted,
+    NotFound,
+    Failed(String),
+}
+
+/// Delete a single resource of an instance, in the foreground so dependents are
+/// cleaned up before the owner disappears.
+pub async fn kubernetes_delete_resource(
+    kubeclient: &Client,
+    api_discovery: &DiscoveryCache,
+    resource: &InstanceResource,
+) -> DeleteOutcome {
+    let (ar, caps) = match api_discovery.resolve_gvk(kubeclient, &resource.gvk).await {
+        Ok(Some(resolved)) => resolved,
+        Ok(None) => return DeleteOutcome::Failed(format!("Unknown kind {}", resource.gvk.kind)),
+        Err(err) => return DeleteOutcome::Failed(err.to_string()),
+    };
+    let api = dynamic_api(
+        ar,
+        caps,
+        kubeclient.clone(),
+        resource.namespace.as_deref(),
+        false,
+    );
+    let started = Instant::now();
+    let delete_result = in_span(
+        "kubernetes.delete",
+        vec![
+            KeyValue::new("k8s.kind", resource.gvk.kind.clone()),
+            KeyValue::new("k8s.name", resource.name.clone()),
+        ],
+        api.delete(&resource.name, &DeleteParams::foreground()),
+    )
+    .await;
+
+    metrics().observe_kubernetes_call("delete", &resource.gvk.kind, started);
+    match delete_result {
+        Ok(_) => DeleteOutcome::Deleted,
+        Err(kube::Error::Api(response)) if response.code == 404 => DeleteOutcome::NotFound,
+        Err(err) => DeleteOutcome::Failed(err.to_string()),
+    }
+}
+
+/// Whether the storage class of the claim allows expanding its volume in place.
+pub async fn volume_expansion_allowed(
+    kubeclient: &Client,
+    pvc: &PersistentVolumeClaim,
+) -> Result<bool, kube::Error> {
+    let Some(storage_class) = pvc
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.storage_class_name.as_ref())
+    else {
+        return Ok(false);
+    };
+    let api: Api<StorageClass> = Api::all(kubeclient.clone());
+    let started = Instant::now();
+    let storage_class = api.get_opt(storage_class).await;
+
+    metrics().observe_kubernetes_call("get", "StorageClass", started);
+    Ok(storage_class?
+        .and_then(|storage_class| storage_class.allow_volume_expansion)
+        .unwrap_or(false))
+}
+
+/// Request a new size for the claim, the volume is then expanded in the background.
+pub async fn kubernetes_resize_volume(
+    kubeclient: &Client,
+    pvc_name: &str,
+    size: &str,
+) -> Result<PersistentVolumeClaim, kube::Error> {
+    let api: Api<PersistentVolumeClaim> = Api::default_namespaced(kubeclient.clone());
+    let patch = serde_json::json!({
+        "spec": { "resources": { "requests": { "storage": size } } }
+    });
+    let started = Instant::now();
+    let result = api
+        .patch(pvc_name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await;
+
+    metrics().observe_kubernetes_call("patch", "PersistentVolumeClaim", started);
+    result
+}
+
+/// Record the size the volumes of the instance were resized to on its StatefulSet.
+pub async fn kubernetes_record_instance_size(
+    kubeclient: &Client,
+    instance_name: &str,
+    size: &str,
+) -> Result<(), kube::Error> {
+    let api: Api<StatefulSet> = Api::default_namespaced(kubeclient.clone());
+    let patch = serde_json::json!({
+        "metadata": { "annotations": { INSTANCE_SIZE_ANNOTATION: size } }
+    });
+    let started = Instant::now();
+    let result = api
+        .patch(
+            &format!("moonscale-instance-{}", instance_name),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await;
+
+    metrics().observe_kubernetes_call("patch", "StatefulSet", started);
+    result.map(|_| ())
+}
+
+/// Get a single resource of an instance, `None` if it doesn't exist.
+pub async fn kubernetes_get_resource(
+    kubeclient: &Client,
+    api_discovery: &DiscoveryCache,
+    resource: &InstanceResource,
+) -> Result<Option<DynamicObject>, anyhow::Error> {
+    let (ar, caps) = api_discovery
+        .resolve_gvk(kubeclient, &resource.gvk)
+        .await?
+        .with_context(|| format!("Unknown kind {}", resource.gvk.kind))?;
+    let api = dynamic_api(
+        ar,
+        caps,
+        kubeclient.clone(),
+        resource.namespace.as_deref(),
+        false,
+    );
+    let started = Instant::now();
+    let get_result = api.get_opt(&resource.name).await;
+
+    metrics().observe_kubernetes_call("get", &resource.gvk.kind, started);
+    Ok(get_result?)
+}
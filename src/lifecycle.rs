@@ -0,0 +1,335 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use k8s_openapi::api::apps::v1::StatefulSet;
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api,
+};
+use log::{info, warn};
+use rocket::futures::StreamExt;
+use rocket::tokio::{
+    select,
+    sync::broadcast,
+    time::{interval, MissedTickBehavior},
+};
+
+use crate::context::Context;
+use crate::kubernetes::{
+    instance_created_at, instance_expires_at, instance_owner, instance_phase,
+    publish_lifecycle_event, InstanceEvent, INSTANCE_READY_TIMEOUT,
+};
+use crate::models::{
+    database::DatabasePhase,
+    lifecycle::{LifecycleEventModel, LifecycleEventType},
+};
+
+/// How many events are kept to resume streams from a `Last-Event-ID`.
+const EVENT_HISTORY_SIZE: usize = 1000;
+/// How often the readiness and expiry deadlines of the instances are checked.
+const DEADLINE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Fans out instance lifecycle events to the connected streams, keeping the most
+/// recent ones so a client can resume where it left off.
+pub struct LifecycleHub {
+    sender: broadcast::Sender<LifecycleEventModel>,
+    history: Mutex<VecDeque<LifecycleEventModel>>,
+    next_id: AtomicU64,
+}
+
+impl Default for LifecycleHub {
+    fn default() -> Self {
+        // Ids start from the current time so they keep increasing across restarts
+        let first_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+
+        LifecycleHub {
+            sender: broadcast::channel(256).0,
+            history: Mutex::new(VecDeque::with_capacity(EVENT_HISTORY_SIZE)),
+            next_id: AtomicU64::new(first_id),
+        }
+    }
+}
+
+impl LifecycleHub {
+    fn publish(&self, event: LifecycleEventType, state: &InstanceState, instance: &str) {
+        let event = LifecycleEventModel {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            event,
+            instance: instance.to_owned(),
+            owner: state.owner.clone(),
+            timestamp: humantime::format_rfc3339(SystemTime::now()).to_string(),
+            expires_at: state
+                .expires_at
+                .map(|expires_at| humantime::format_rfc3339(expires_at).to_string()),
+        };
+        let mut history = self.history.lock().unwrap();
+
+        info!(
+            instance = instance;
+            "Instance {} lifecycle event: {}", instance, event.event.as_str()
+        );
+        if history.len() == EVENT_HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(event.clone());
+        // Sending only fails when nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe to the live events, along with the buffered events following
+    /// `last_event_id` when resuming a stream.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (
+        Vec<LifecycleEventModel>,
+        broadcast::Receiver<LifecycleEventModel>,
+    ) {
+        // Subscribe while holding the history so no event falls between the two
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let replay = match last_event_id {
+            Some(last_event_id) => history
+                .iter()
+                .filter(|event| event.id > last_event_id)
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+
+        (replay, receiver)
+    }
+}
+
+/// What we last observed of an instance, to turn StatefulSet updates into transitions.
+struct InstanceState {
+    owner: Option<String>,
+    ready: bool,
+    paused: bool,
+    /// When the instance was created, or last resumed, to know when it's late.
+    started_at: Option<SystemTime>,
+    expires_at: Option<SystemTime>,
+    failed: bool,
+    expiring: bool,
+}
+
+impl InstanceState {
+    fn from_statefulset(sts: &StatefulSet) -> Self {
+        InstanceState {
+            owner: instance_owner(sts),
+            ready: instance_phase(sts) == DatabasePhase::Ready,
+            paused: instance_phase(sts) == DatabasePhase::Paused,
+            started_at: instance_created_at(sts),
+            expires_at: instance_expires_at(sts),
+            failed: false,
+            expiring: false,
+        }
+    }
+}
+
+fn instance_name(sts: &StatefulSet) -> Option<String> {
+    sts.metadata
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get("app.kubernetes.io/instance"))
+        .cloned()
+}
+
+/// Turns the watched StatefulSets into lifecycle events. While `silent`, the state is
+/// only recorded, so the initial listing doesn't replay the history of every instance.
+struct InstanceTracker<'a> {
+    context: &'a Context,
+    instances: HashMap<String, InstanceState>,
+}
+
+impl InstanceTracker<'_> {
+    fn emit(&self, event: LifecycleEventType, instance: &str, silent: bool) {
+        if !silent {
+            self.context
+                .lifecycle
+                .publish(event, &self.instances[instance], instance);
+        }
+    }
+
+    fn applied(&mut self, sts: &StatefulSet, silent: bool) {
+        let Some(name) = instance_name(sts) else {
+            return;
+        };
+        let mut observed = InstanceState::from_statefulset(sts);
+
+        match self.instances.get(&name) {
+            None => {
+                let (ready, paused) = (observed.ready, observed.paused);
+
+                self.instances.insert(name.clone(), observed);
+                self.emit(LifecycleEventType::Created, &name, silent);
+                if ready {
+                    self.emit(LifecycleEventType::Ready, &name, silent);
+                }
+                if paused {
+                    self.emit(LifecycleEventType::Paused, &name, silent);
+                }
+            }
+            Some(known) => {
+                let became_ready = observed.ready && !known.ready;
+                let became_paused = observed.paused && !known.paused;
+
+                observed.failed = known.failed && !became_ready && !known.paused;
+                observed.expiring = known.expiring;
+                if known.paused && !observed.paused {
+                    observed.started_at = Some(SystemTime::now());
+                } else {
+                    observed.started_at = known.started_at;
+                }
+                self.instances.insert(name.clone(), observed);
+                if became_ready {
+                    self.emit(LifecycleEventType::Ready, &name, silent);
+                }
+                if became_paused {
+                    self.emit(LifecycleEventType::Paused, &name, silent);
+                }
+            }
+        }
+    }
+
+    async fn deleted(&mut self, name: &str) {
+        let Some(state) = self.instances.get(name) else {
+            return;
+        };
+        // Instances gone past their TTL were removed by the janitor
+        let expired = state
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= SystemTime::now());
+
+        self.emit(LifecycleEventType::Deleted, name, false);
+        self.instances.remove(name);
+        if expired {
+            publish_lifecycle_event(
+                &self.context.kubernetes_client,
+                name,
+                InstanceEvent::Expired,
+                format!("Instance {} expired and was deleted", name),
+            )
+            .await;
+        }
+    }
+
+    /// The watch was (re)started, diff the full listing against what we knew.
+    async fn restarted(&mut self, statefulsets: &[StatefulSet], silent: bool) {
+        let listed: Vec<String> = statefulsets.iter().filter_map(instance_name).collect();
+        let gone: Vec<String> = self
+            .instances
+            .keys()
+            .filter(|name| !listed.contains(name))
+            .cloned()
+            .collect();
+
+        for name in gone {
+            self.deleted(&name).await;
+        }
+        for sts in statefulsets {
+            self.applied(sts, silent);
+        }
+        self.check_deadlines(silent).await;
+    }
+
+    /// Flag the instances that didn't become ready in time or are about to expire.
+    async fn check_deadlines(&mut self, silent: bool) {
+        let now = SystemTime::now();
+        let expiry_warning = self.context.config.expiry_warning;
+        let mut events = vec![];
+
+        for (name, state) in self.instances.iter_mut() {
+            let ready_deadline = state
+                .started_at
+                .map(|started_at| started_at + INSTANCE_READY_TIMEOUT);
+
+            if !state.ready
+                && !state.paused
+                && !state.failed
+                && ready_deadline.is_some_and(|d| d <= now)
+            {
+                state.failed = true;
+                events.push((LifecycleEventType::Failed, name.clone()));
+            }
+            if !state.expiring
+                && state
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at <= now + expiry_warning)
+            {
+                state.expiring = true;
+                events.push((LifecycleEventType::ExpiringSoon, name.clone()));
+            }
+        }
+        if silent {
+            return;
+        }
+        for (event, name) in events {
+            self.emit(event, &name, false);
+            let (instance_event, note) = match event {
+                LifecycleEventType::Failed => (
+                    InstanceEvent::ReadyTimeout,
+                    format!(
+                        "Instance {} didn't become ready within {}s",
+                        name,
+                        INSTANCE_READY_TIMEOUT.as_secs()
+                    ),
+                ),
+                _ => (
+                    InstanceEvent::ExpiringSoon,
+                    format!("Instance {} expires soon", name),
+                ),
+            };
+
+            publish_lifecycle_event(&self.context.kubernetes_client, &name, instance_event, note)
+                .await;
+        }
+    }
+}
+
+/// Watch the managed StatefulSets and publish their lifecycle changes on the hub.
+pub async fn watch_instances(context: Context) {
+    let api: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let mut events = watcher(
+        api,
+        watcher::Config::default().labels("app.kubernetes.io/managed-by=Moonscale"),
+    )
+    .default_backoff()
+    .boxed();
+    let mut ticker = interval(DEADLINE_CHECK_INTERVAL);
+    let mut tracker = InstanceTracker {
+        context: &context,
+        instances: HashMap::new(),
+    };
+    let mut synced = false;
+
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        select! {
+            event = events.next() => match event {
+                Some(Ok(watcher::Event::Applied(sts))) => tracker.applied(&sts, !synced),
+                Some(Ok(watcher::Event::Deleted(sts))) => {
+                    if let Some(name) = instance_name(&sts) {
+                        tracker.deleted(&name).await;
+                    }
+                }
+                Some(Ok(watcher::Event::Restarted(statefulsets))) => {
+                    tracker.restarted(&statefulsets, !synced).await;
+                    synced = true;
+                }
+                Some(Err(err)) => warn!("Failed to watch managed instances: {}", err),
+                None => break,
+            },
+            _ = ticker.tick(), if synced => tracker.check_deadlines(false).await,
+        }
+    }
+    warn!("Stopped watching managed instances");
+}
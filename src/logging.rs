@@ -0,0 +1,244 @@
+use std::{
+    env,
+    fmt::Write,
+    sync::{OnceLock, RwLock},
+    time::SystemTime,
+};
+
+use k8s_openapi::serde_json::{self, Map, Value};
+use log::{
+    kv::{Error as KvError, Key, Value as KvValue, VisitSource},
+    LevelFilter, Record,
+};
+use regex::Regex;
+
+use crate::telemetry::{current_request_info, current_trace_id};
+
+const REDACTED: &str = "[REDACTED]";
+
+enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Secrets that must never appear in logs, eg: the configured API keys.
+fn registered_secrets() -> &'static RwLock<Vec<String>> {
+    static SECRETS: OnceLock<RwLock<Vec<String>>> = OnceLock::new();
+
+    SECRETS.get_or_init(|| RwLock::new(vec![]))
+}
+
+/// Register a secret value, every occurrence of it is redacted from log lines.
+pub fn register_secret(secret: &str) {
+    if !secret.is_empty() {
+        registered_secrets()
+            .write()
+            .unwrap()
+            .push(secret.to_owned());
+    }
+}
+
+/// Redact bearer tokens, anything that looks like a password/secret/token assignment
+/// and registered secrets from a log line. Assignments cover the credentials of
+/// `Authorization: Basic ...` headers and Debug formatted values, eg:
+/// `"password": String("...")`.
+fn redact(line: &str) -> String {
+    static PATTERNS: OnceLock<[Regex; 2]> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(
+                r#"(?i)(\w*(?:password|secret|token|authorization)\w*"?\s*[:=]\s*(?:\w+\(\s*)*"?(?:(?:basic|bearer)\s+)?)[^\s"',;})]+"#,
+            )
+            .unwrap(),
+            Regex::new(r#"(?i)(bearer\s+)[^\s"',;]+"#).unwrap(),
+        ]
+    });
+    let mut redacted = line.to_owned();
+
+    for pattern in patterns.iter() {
+        redacted = pattern
+            .replace_all(&redacted, format!("${{1}}{}", REDACTED))
+            .into_owned();
+    }
+    for secret in registered_secrets().read().unwrap().iter() {
+        redacted = redacted.replace(secret.as_str(), REDACTED);
+    }
+    redacted
+}
+
+/// Collects the structured key/values of a record, eg: `info!(instance = name; "...")`.
+struct FieldCollector(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KvValue<'kvs>) -> Result<(), KvError> {
+        self.0.push((key.as_str().to_owned(), value.to_string()));
+        Ok(())
+    }
+}
+
+fn record_fields(record: &Record) -> Vec<(String, String)> {
+    let mut collector = FieldCollector(vec![]);
+    let mut fields = vec![];
+
+    if let Some(trace_id) = current_trace_id() {
+        fields.push(("trace_id".to_owned(), trace_id));
+    }
+    if let Some(request) = current_request_info() {
+        fields.push(("request_id".to_owned(), request.request_id));
+        fields.push(("route".to_owned(), request.route));
+    }
+    let _ = record.key_values().visit(&mut collector);
+    fields.extend(collector.0);
+    fields
+}
+
+fn format_text(message: &std::fmt::Arguments, record: &Record) -> String {
+    let mut line = format!(
+        "[{} {} {}] {}",
+        humantime::format_rfc3339(SystemTime::now()),
+        record.level(),
+        record.target(),
+        message
+    );
+
+    for (key, value) in record_fields(record) {
+        let _ = write!(line, " {}={}", key, value);
+    }
+    redact(&line)
+}
+
+fn format_json(message: &std::fmt::Arguments, record: &Record) -> String {
+    let mut line = Map::new();
+
+    line.insert(
+        "timestamp".to_owned(),
+        Value::String(humantime::format_rfc3339(SystemTime::now()).to_string()),
+    );
+    line.insert(
+        "level".to_owned(),
+        Value::String(record.level().to_string()),
+    );
+    line.insert(
+        "target".to_owned(),
+        Value::String(record.target().to_owned()),
+    );
+    line.insert(
+        "message".to_owned(),
+        Value::String(redact(&message.to_string())),
+    );
+    for (key, value) in record_fields(record) {
+        line.insert(key, Value::String(redact(&value)));
+    }
+    serde_json::to_string(&line).unwrap_or_default()
+}
+
+/// Parse a level directive list, eg: `info,kube=warn,moonscale::routes=debug`.
+/// Entries without a module set the default level.
+fn parse_level_directives(directives: &str) -> (LevelFilter, Vec<(String, LevelFilter)>) {
+    let mut default_level = if cfg!(debug_assertions) {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+    let mut module_levels = vec![];
+
+    for directive in directives.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            Some((module, level)) => match level.parse() {
+                Ok(level) => module_levels.push((module.to_owned(), level)),
+                Err(_) => eprintln!("Ignoring invalid log level directive {}", directive),
+            },
+            None if directive.is_empty() => {}
+            None => match directive.parse() {
+                Ok(level) => default_level = level,
+                Err(_) => eprintln!("Ignoring invalid log level {}", directive),
+            },
+        }
+    }
+    (default_level, module_levels)
+}
+
+/// Configure the global logger from `MOONSCALE_LOG_LEVEL` (a directive list such as
+/// `info,kube=warn`) and `MOONSCALE_LOG_FORMAT` (`text` or `json`).
+pub fn setup_logger() -> Result<(), log::SetLoggerError> {
+    let (default_level, module_levels) =
+        parse_level_directives(&env::var("MOONSCALE_LOG_LEVEL").unwrap_or_default());
+    let format = match env::var("MOONSCALE_LOG_FORMAT").as_deref() {
+        Ok("json") => LogFormat::Json,
+        _ => LogFormat::Text,
+    };
+    let mut dispatch = fern::Dispatch::new()
+        .format(move |out, message, record| {
+            let line = match format {
+                LogFormat::Json => format_json(message, record),
+                LogFormat::Text => format_text(message, record),
+            };
+
+            out.finish(format_args!("{}", line))
+        })
+        .level(default_level);
+
+    for (module, level) in module_levels {
+        dispatch = dispatch.level_for(module, level);
+    }
+    dispatch.chain(std::io::stdout()).apply()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_authorization_headers() {
+        assert_eq!(
+            redact("Authorization: Basic cm9vdDpwdw=="),
+            "Authorization: Basic [REDACTED]"
+        );
+        assert_eq!(
+            redact("authorization=bearer abc.def"),
+            "authorization=bearer [REDACTED]"
+        );
+        assert_eq!(
+            redact(r#"headers: {"authorization": "Basic cm9vdDpwdw=="}"#),
+            r#"headers: {"authorization": "Basic [REDACTED]"}"#
+        );
+        assert_eq!(redact("sent Bearer abc123"), "sent Bearer [REDACTED]");
+    }
+
+    #[test]
+    fn redacts_assignments() {
+        assert_eq!(
+            redact("password=hunter2 user=root"),
+            "password=[REDACTED] user=root"
+        );
+        assert_eq!(
+            redact(r#"{"api_token":"abc","name":"foo"}"#),
+            r#"{"api_token":"[REDACTED]","name":"foo"}"#
+        );
+    }
+
+    #[test]
+    fn redacts_debug_formatted_values() {
+        assert_eq!(
+            redact(r#"{"mysql-root-password": String("abc")}"#),
+            r#"{"mysql-root-password": String("[REDACTED]")}"#
+        );
+        assert_eq!(
+            redact(r#"Secret { token: Some("abc") }"#),
+            r#"Secret { token: Some("[REDACTED]") }"#
+        );
+    }
+
+    #[test]
+    fn redacts_registered_secrets() {
+        register_secret("s3cr3t-key");
+        assert_eq!(redact("called with s3cr3t-key"), "called with [REDACTED]");
+    }
+
+    #[test]
+    fn keeps_unrelated_lines() {
+        let line = "Created moonscale instance foo-pr-1 in 12ms";
+
+        assert_eq!(redact(line), line);
+    }
+}
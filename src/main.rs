@@ -1,60 +1,183 @@
-use std::env;
+use std::{collections::HashMap, env, path::PathBuf, sync::Arc, time::Duration};
 
-use crate::routes::{create_database::*, delete_database::*, list_database::*};
+use crate::middlewares::{metrics::RequestMetrics, tracing::RequestTracing};
+use crate::routes::{
+    activator::*, audit::*, create_database::*, delete_database::*, dry_run_database::*, events::*,
+    get_database::*, integrations::*, list_database::*, metrics::*, operations::*,
+    pause_database::*, probes::*, proxy::*, quota::*, resize_database::*, webhooks::*,
+};
+use activator::{Activator, ActivatorConfig};
 use anyhow::Result;
+use audit::{AuditLog, AuditSink};
 use context::Config;
+use discovery::DiscoveryCache;
+use health::HealthChecker;
+use lifecycle::LifecycleHub;
 use log::{error, info};
-use rocket::get;
-use rocket::http::Status;
-use rocket_okapi::{openapi, openapi_get_routes, swagger_ui::*};
+use logging::setup_logger;
+use metadata::is_valid_label_value;
+use operations::OperationStore;
+use proxy::{PasswordCache, ProxyConfig, ProxyMode};
+use quota::QuotaLimits;
+use rocket::routes;
+use rocket_okapi::{openapi_get_routes, swagger_ui::*};
+use template::template_api_groups;
+use webhooks::WebhookStore;
 
+mod activator;
+mod audit;
+mod autopause;
+mod cli;
 mod context;
+mod crypto;
+mod discovery;
+mod dry_run;
+mod health;
 mod kubernetes;
+mod lifecycle;
+mod logging;
+mod metadata;
+mod metrics;
 mod middlewares;
 mod models;
+mod operations;
+mod presets;
+mod profiles;
+mod proxy;
+mod quantity;
+mod quota;
 mod routes;
+mod telemetry;
+mod template;
+mod webhooks;
 
-/// # Get if service is ready
-///
-/// 200 if service is ready, used for kubernetes probes
-#[openapi(tag = "Kubernetes Probes")]
-#[get("/readyz")]
-fn readyz_route() -> Status {
-    Status::Ok
+fn build_api_keys() -> HashMap<String, String> {
+    let mut api_keys = HashMap::new();
+
+    if let Ok(api_key) = env::var("MOONSCALE_API_KEY") {
+        if !api_key.is_empty() {
+            api_keys.insert(api_key, "default".to_owned());
+        }
+    }
+    // Additional named keys, formatted as a comma separated list of `principal:key`
+    for entry in env::var("MOONSCALE_API_KEYS")
+        .unwrap_or_default()
+        .split(',')
+    {
+        match entry.trim().split_once(':') {
+            Some((principal, key)) if !principal.is_empty() && !key.is_empty() => {
+                // Principals are stored as the owner label of the instances they create
+                if !is_valid_label_value(principal) {
+                    error!(
+                        "Ignoring MOONSCALE_API_KEYS entry for {}, principals must be valid label values",
+                        principal
+                    );
+                    continue;
+                }
+                api_keys.insert(key.to_owned(), principal.to_owned());
+            }
+            _ if entry.trim().is_empty() => {}
+            _ => error!("Ignoring malformed MOONSCALE_API_KEYS entry, expected principal:key"),
+        }
+    }
+    api_keys
 }
 
-fn setup_logger() -> Result<(), log::SetLoggerError> {
-    fern::Dispatch::new()
-        // Perform allocation-free log formatting
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{} {} {}] {}",
-                humantime::format_rfc3339(std::time::SystemTime::now()),
-                record.level(),
-                record.target(),
-                message
-            ))
-        })
-        .level(if cfg!(debug_assertions) {
-            // TODO: Add a flag to enable debug logging
-            log::LevelFilter::Debug
-        } else {
-            log::LevelFilter::Debug
+fn build_audit_sinks() -> Vec<AuditSink> {
+    let audit_file =
+        env::var("MOONSCALE_AUDIT_FILE").unwrap_or("/var/log/moonscale/audit.jsonl".to_owned());
+
+    env::var("MOONSCALE_AUDIT_SINKS")
+        .unwrap_or("stdout".to_owned())
+        .split(',')
+        .filter_map(|sink| match sink.trim() {
+            "stdout" => Some(AuditSink::Stdout),
+            "file" => Some(AuditSink::File(PathBuf::from(&audit_file))),
+            "events" => Some(AuditSink::KubernetesEvents),
+            "" => None,
+            other => {
+                error!(
+                    "Unknown audit sink {}, expected stdout, file or events",
+                    other
+                );
+                std::process::exit(1);
+            }
         })
-        .chain(std::io::stdout())
-        .apply()
+        .collect()
 }
 
-fn build_config() -> Result<Config, ()> {
-    let env_api_key = env::var("MOONSCALE_API_KEY");
+/// Parse an optional limit, unset or empty values disable it.
+fn parse_limit<T>(name: &str, parse: impl Fn(&str) -> Result<T, String>) -> Option<T> {
+    let value = env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())?;
 
-    if env_api_key.is_err() || env_api_key.clone().unwrap() == "" {
-        error!("Invalid API key, did you set the MOONSCALE_API_KEY environment variable to a non-empty string ?");
-        return Err(());
+    Some(parse(value.trim()).unwrap_or_else(|err| {
+        error!("Failed to parse {}: {}", name, err);
+        std::process::exit(1);
+    }))
+}
+
+fn build_quota_limits() -> QuotaLimits {
+    let parse_count = |value: &str| value.parse::<usize>().map_err(|err| err.to_string());
+
+    QuotaLimits {
+        max_instances: parse_limit("MOONSCALE_MAX_INSTANCES", parse_count),
+        max_instances_per_owner: parse_limit("MOONSCALE_MAX_INSTANCES_PER_OWNER", parse_count),
+        max_storage: parse_limit("MOONSCALE_MAX_STORAGE", quantity::parse_quantity),
+        max_ttl: parse_limit("MOONSCALE_MAX_TTL", parse_count),
     }
+}
+
+fn build_proxy_config() -> Option<ProxyConfig> {
+    let mode = env::var("MOONSCALE_PROXY_MODE")
+        .ok()
+        .filter(|mode| !mode.is_empty())?;
+    let mode = ProxyMode::from_name(&mode).unwrap_or_else(|| {
+        error!(
+            "Failed to parse MOONSCALE_PROXY_MODE: expected path or host, got {}",
+            mode
+        );
+        std::process::exit(1);
+    });
+    let url = env::var("MOONSCALE_PROXY_URL").unwrap_or_default();
 
+    if mode == ProxyMode::Path && url.is_empty() {
+        error!("MOONSCALE_PROXY_URL must be set to the public URL of moonscale in path mode");
+        std::process::exit(1);
+    }
+    Some(ProxyConfig { mode, url })
+}
+
+fn build_config() -> Result<Config, ()> {
+    let api_keys = build_api_keys();
+
+    let github_webhook_secret = env::var("MOONSCALE_GITHUB_WEBHOOK_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty());
+    let gitlab_webhook_token = env::var("MOONSCALE_GITLAB_WEBHOOK_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+
+    for secret in api_keys
+        .keys()
+        .chain(github_webhook_secret.iter())
+        .chain(gitlab_webhook_token.iter())
+    {
+        logging::register_secret(secret);
+    }
     Ok(Config {
-        api_key: env_api_key.unwrap(),
+        api_keys,
+        github_webhook_secret,
+        gitlab_webhook_token,
+        admin_principals: env::var("MOONSCALE_ADMIN_PRINCIPALS")
+            .unwrap_or("default".to_owned())
+            .split(',')
+            .map(str::trim)
+            .filter(|principal| !principal.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        namespace: env::var("MOONSCALE_NAMESPACE").unwrap_or("moonscale".to_owned()),
         ingress_domain: env::var("MOONSCALE_INGRESS_DOMAIN").unwrap_or("example.com".to_owned()),
         resource_ttl: env::var("MOONSCALE_RESOURCE_TTL")
             .unwrap_or("3600".to_owned())
@@ -63,9 +186,87 @@ fn build_config() -> Result<Config, ()> {
                 error!("Failed to parse MOONSCALE_RESOURCE_TTL: {}", err);
                 std::process::exit(1);
             }),
+        audit_sinks: build_audit_sinks(),
+        audit_history_size: env::var("MOONSCALE_AUDIT_HISTORY_SIZE")
+            .unwrap_or("1000".to_owned())
+            .parse()
+            .unwrap_or_else(|err| {
+                error!("Failed to parse MOONSCALE_AUDIT_HISTORY_SIZE: {}", err);
+                std::process::exit(1);
+            }),
+        health_cache_ttl: Duration::from_secs(
+            env::var("MOONSCALE_HEALTH_CACHE_TTL")
+                .unwrap_or("10".to_owned())
+                .parse()
+                .unwrap_or_else(|err| {
+                    error!("Failed to parse MOONSCALE_HEALTH_CACHE_TTL: {}", err);
+                    std::process::exit(1);
+                }),
+        ),
+        discovery_ttl: Duration::from_secs(
+            env::var("MOONSCALE_DISCOVERY_TTL")
+                .unwrap_or("300".to_owned())
+                .parse()
+                .unwrap_or_else(|err| {
+                    error!("Failed to parse MOONSCALE_DISCOVERY_TTL: {}", err);
+                    std::process::exit(1);
+                }),
+        ),
+        operation_retention: Duration::from_secs(
+            env::var("MOONSCALE_OPERATION_RETENTION")
+                .unwrap_or("86400".to_owned())
+                .parse()
+                .unwrap_or_else(|err| {
+                    error!("Failed to parse MOONSCALE_OPERATION_RETENTION: {}", err);
+                    std::process::exit(1);
+                }),
+        ),
+        expiry_warning: Duration::from_secs(
+            env::var("MOONSCALE_EXPIRY_WARNING")
+                .unwrap_or("600".to_owned())
+                .parse()
+                .unwrap_or_else(|err| {
+                    error!("Failed to parse MOONSCALE_EXPIRY_WARNING: {}", err);
+                    std::process::exit(1);
+                }),
+        ),
+        quota: build_quota_limits(),
+        auto_pause: parse_limit("MOONSCALE_AUTO_PAUSE", |value| {
+            value.parse::<usize>().map_err(|err| err.to_string())
+        }),
+        activator: env::var("MOONSCALE_ACTIVATOR_SERVICE")
+            .ok()
+            .filter(|service| !service.is_empty())
+            .map(|service| ActivatorConfig {
+                service,
+                port: env::var("MOONSCALE_ACTIVATOR_PORT")
+                    .unwrap_or("8000".to_owned())
+                    .parse()
+                    .unwrap_or_else(|err| {
+                        error!("Failed to parse MOONSCALE_ACTIVATOR_PORT: {}", err);
+                        std::process::exit(1);
+                    }),
+            }),
+        proxy: build_proxy_config(),
+        profiles: profiles::load_profiles(
+            env::var("MOONSCALE_PROFILES").ok().as_deref(),
+            &env::var("MOONSCALE_STORAGE_CLASS").unwrap_or("cinder-generic-nvme".to_owned()),
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to load MOONSCALE_PROFILES: {}", err);
+            std::process::exit(1);
+        }),
     })
 }
 
+/// Build the kubernetes client, every namespaced API defaults to the configured namespace.
+async fn build_kubernetes_client(namespace: &str) -> Result<kube::Client, anyhow::Error> {
+    let mut kube_config = kube::Config::infer().await?;
+
+    kube_config.default_namespace = namespace.to_owned();
+    Ok(kube::Client::try_from(kube_config)?)
+}
+
 #[rocket::main]
 async fn main() -> Result<(), ()> {
     if setup_logger().is_err() {
@@ -77,25 +278,89 @@ async fn main() -> Result<(), ()> {
         error!("Couldn't build configuration, check logs for error.");
         return Err(());
     }
-    let context = context::Context {
-        database_template_yaml_raw: include_str!("../resources/template.yml").to_owned(),
-        kubernetes_client: kube::Client::try_default().await.unwrap_or_else(|err| {
+    let config = config.unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let kubernetes_client = build_kubernetes_client(&config.namespace)
+        .await
+        .unwrap_or_else(|err| {
             error!("Failed to create kubernetes client: {}", err);
             std::process::exit(1);
-        }),
-        config: config.unwrap(),
+        });
+    let database_template_yaml_raw = include_str!("../resources/template.yml").to_owned();
+
+    if args.first().map(String::as_str) == Some("template") {
+        return cli::template_command(
+            &config,
+            &kubernetes_client,
+            &database_template_yaml_raw,
+            &args[1..],
+        )
+        .await;
+    }
+    if config.api_keys.is_empty() {
+        error!("Invalid API key, did you set the MOONSCALE_API_KEY environment variable to a non-empty string ?");
+        return Err(());
+    }
+    let operations = OperationStore::load(&kubernetes_client, config.operation_retention).await;
+    let webhooks = WebhookStore::load(&kubernetes_client).await;
+    let discovery = DiscoveryCache::new(
+        config.discovery_ttl,
+        template_api_groups(&config, &database_template_yaml_raw),
+    );
+    let context = context::Context {
+        database_template_yaml_raw,
+        kubernetes_client,
+        audit: Arc::new(AuditLog::new(
+            config.audit_sinks.clone(),
+            config.audit_history_size,
+        )),
+        health: Arc::new(HealthChecker::new(config.health_cache_ttl)),
+        discovery: Arc::new(discovery),
+        operations: Arc::new(operations),
+        lifecycle: Arc::new(LifecycleHub::default()),
+        webhooks: Arc::new(webhooks),
+        activator: Arc::new(Activator::default()),
+        proxy_passwords: Arc::new(PasswordCache::default()),
+        quota_lock: Arc::new(rocket::tokio::sync::Mutex::new(())),
+        config,
     };
 
+    let otlp_endpoint = env::var("MOONSCALE_OTLP_ENDPOINT")
+        .or(env::var("OTEL_EXPORTER_OTLP_ENDPOINT"))
+        .ok();
+
+    if let Err(err) = telemetry::setup_tracing(otlp_endpoint.as_deref()) {
+        error!("Failed to setup OpenTelemetry tracing: {}", err);
+        return Err(());
+    }
+
     info!("Starting moonscale server with context:");
     info!("\tIngress domain: {}", context.config.ingress_domain);
+    info!("\tNamespace: {}", context.config.namespace);
 
+    rocket::tokio::spawn(lifecycle::watch_instances(context.clone()));
+    rocket::tokio::spawn(webhooks::dispatch_webhooks(context.clone()));
+    rocket::tokio::spawn(autopause::auto_pause_instances(context.clone()));
     let launch_result = rocket::build()
         .mount(
             "/api",
             openapi_get_routes![
                 route_create_database,
+                route_dry_run_database,
                 route_list_database,
-                route_delete_database
+                route_get_database,
+                route_delete_database,
+                route_resize_database,
+                route_pause_database,
+                route_resume_database,
+                route_list_audit,
+                route_list_operations,
+                route_get_operation,
+                route_get_quota,
+                route_create_webhook,
+                route_list_webhooks,
+                route_delete_webhook,
+                route_list_webhook_deliveries
             ],
         )
         .mount(
@@ -105,7 +370,32 @@ async fn main() -> Result<(), ()> {
                 ..Default::default()
             }),
         )
-        .mount("/", openapi_get_routes![readyz_route])
+        .mount(
+            "/",
+            openapi_get_routes![readyz_route, livez_route, healthz_route],
+        )
+        .mount(
+            "/api",
+            routes![
+                route_database_events,
+                route_github_integration,
+                route_gitlab_integration
+            ],
+        )
+        .mount("/", routes![route_metrics])
+        .mount(
+            "/",
+            routes![
+                route_activator_get,
+                route_activator_post,
+                route_activator_put,
+                route_activator_patch,
+                route_activator_delete,
+                route_proxy_database
+            ],
+        )
+        .attach(RequestMetrics)
+        .attach(RequestTracing)
         .manage(context)
         .launch()
         .await;
@@ -113,5 +403,6 @@ async fn main() -> Result<(), ()> {
         Ok(_) => println!("Rocket shut down gracefully."),
         Err(err) => println!("Rocket had an error: {}", err),
     };
+    telemetry::shutdown_tracing();
     Ok(())
 }
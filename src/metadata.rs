@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+
+use crate::kubernetes::INSTANCE_AUTO_PAUSE_ANNOTATION;
+use crate::models::database::ResourcePreset;
+
+/// Prefix given to the user labels and annotations that don't carry one.
+pub const USER_METADATA_PREFIX: &str = "user.moonscale";
+/// Kubernetes rejects objects whose annotations exceed 256KiB in total.
+const MAX_ANNOTATIONS_SIZE: usize = 256 * 1024;
+/// Prefixes owned by Kubernetes, the janitor and moonscale itself.
+const RESERVED_PREFIXES: [&str; 4] = ["kubernetes.io", "k8s.io", "moonscale", "janitor"];
+
+pub fn is_valid_label_value(value: &str) -> bool {
+    static LABEL_VALUE: OnceLock<Regex> = OnceLock::new();
+    let label_value = LABEL_VALUE
+        .get_or_init(|| Regex::new(r"^([A-Za-z0-9]([-A-Za-z0-9_.]*[A-Za-z0-9])?)?$").unwrap());
+
+    value.len() <= 63 && label_value.is_match(value)
+}
+
+/// A label value that can name something, unlike label values it can't be empty.
+pub fn is_valid_label_name(value: &str) -> bool {
+    !value.is_empty() && is_valid_label_value(value)
+}
+
+fn is_valid_prefix(prefix: &str) -> bool {
+    static SUBDOMAIN: OnceLock<Regex> = OnceLock::new();
+    let subdomain = SUBDOMAIN.get_or_init(|| {
+        Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?(\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*$").unwrap()
+    });
+
+    prefix.len() <= 253 && subdomain.is_match(prefix)
+}
+
+fn is_reserved_prefix(prefix: &str) -> bool {
+    RESERVED_PREFIXES
+        .iter()
+        .any(|reserved| prefix == *reserved || prefix.ends_with(&format!(".{}", reserved)))
+}
+
+/// Whether the key belongs to Kubernetes, the janitor or moonscale rather than a user.
+fn is_system_key(key: &str) -> bool {
+    key.split_once('/')
+        .is_some_and(|(prefix, _)| prefix != USER_METADATA_PREFIX && is_reserved_prefix(prefix))
+}
+
+/// Validate a user provided label or annotation key, giving it the user prefix
+/// when it has none, eg: `team` becomes `user.moonscale/team`.
+fn user_metadata_key(key: &str) -> Result<String, String> {
+    let (prefix, name) = match key.split_once('/') {
+        Some((prefix, name)) => (prefix, name),
+        None => (USER_METADATA_PREFIX, key),
+    };
+
+    if !is_valid_label_name(name) {
+        return Err(format!("Invalid key {:?}", key));
+    }
+    if !is_valid_prefix(prefix) {
+        return Err(format!("Invalid prefix in key {:?}", key));
+    }
+    if prefix != USER_METADATA_PREFIX && is_reserved_prefix(prefix) {
+        return Err(format!("The prefix of key {:?} is reserved", key));
+    }
+    Ok(format!("{}/{}", prefix, name))
+}
+
+/// Labels and annotations added to every resource of an instance. System entries
+/// always win over the template, user entries never override it.
+#[derive(Clone, Default)]
+pub struct InstanceMetadata {
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl InstanceMetadata {
+    /// Validate the labels and annotations requested by the user.
+    pub fn from_user(
+        labels: &BTreeMap<String, String>,
+        annotations: &BTreeMap<String, String>,
+    ) -> Result<InstanceMetadata, String> {
+        let mut metadata = InstanceMetadata::default();
+
+        for (key, value) in labels {
+            if !is_valid_label_value(value) {
+                return Err(format!("Invalid value for label {:?}", key));
+            }
+            metadata
+                .labels
+                .insert(user_metadata_key(key)?, value.clone());
+        }
+        for (key, value) in annotations {
+            metadata
+                .annotations
+                .insert(user_metadata_key(key)?, value.clone());
+        }
+
+        let annotations_size: usize = metadata
+            .annotations
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum();
+
+        if annotations_size > MAX_ANNOTATIONS_SIZE {
+            return Err("The annotations are too large".to_owned());
+        }
+        Ok(metadata)
+    }
+
+    /// Add the metadata moonscale relies on to find, attribute and expire the
+    /// resources of the instance.
+    pub fn with_system_metadata(
+        mut self,
+        instance_name: &str,
+        owner: &str,
+        profile: &str,
+        preset: ResourcePreset,
+        ttl: usize,
+    ) -> InstanceMetadata {
+        self.labels.extend([
+            (
+                "app.kubernetes.io/managed-by".to_owned(),
+                "Moonscale".to_owned(),
+            ),
+            (
+                "app.kubernetes.io/instance".to_owned(),
+                instance_name.to_owned(),
+            ),
+            ("moonscale/owner".to_owned(), owner.to_owned()),
+            ("moonscale/profile".to_owned(), profile.to_owned()),
+            ("moonscale/preset".to_owned(), preset.as_str().to_owned()),
+        ]);
+        self.annotations
+            .insert("janitor/ttl".to_owned(), format!("{}m", ttl));
+        self
+    }
+
+    /// Record how long the instance may stay idle before being paused, when it's
+    /// not disabled.
+    pub fn with_auto_pause(mut self, auto_pause: usize) -> InstanceMetadata {
+        if auto_pause > 0 {
+            self.annotations.insert(
+                INSTANCE_AUTO_PAUSE_ANNOTATION.to_owned(),
+                format!("{}m", auto_pause),
+            );
+        }
+        self
+    }
+
+    /// The user labels and annotations of an existing resource, leaving out the
+    /// ones set by Kubernetes, the janitor or moonscale.
+    pub fn from_object(metadata: &ObjectMeta) -> InstanceMetadata {
+        InstanceMetadata {
+            labels: user_entries(metadata.labels.iter().flatten()),
+            annotations: user_entries(metadata.annotations.iter().flatten()),
+        }
+    }
+
+    /// The user labels and annotations, without the system ones.
+    pub fn user_metadata(&self) -> InstanceMetadata {
+        InstanceMetadata {
+            labels: user_entries(&self.labels),
+            annotations: user_entries(&self.annotations),
+        }
+    }
+
+    /// Merge the labels and annotations into the metadata of a rendered document,
+    /// and of the volume claims it templates.
+    pub fn inject(&self, doc: &mut Value) {
+        merge_metadata(doc, "labels", &self.labels);
+        merge_metadata(doc, "annotations", &self.annotations);
+
+        let claim_templates = doc
+            .get_mut("spec")
+            .and_then(|spec| spec.get_mut("volumeClaimTemplates"))
+            .and_then(Value::as_sequence_mut);
+
+        for claim_template in claim_templates.into_iter().flatten() {
+            merge_metadata(claim_template, "labels", &self.labels);
+            merge_metadata(claim_template, "annotations", &self.annotations);
+        }
+    }
+}
+
+fn user_entries<'a>(
+    entries: impl IntoIterator<Item = (&'a String, &'a String)>,
+) -> BTreeMap<String, String> {
+    entries
+        .into_iter()
+        .filter(|(key, _)| key.contains('/') && !is_system_key(key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+fn merge_metadata(doc: &mut Value, field: &str, entries: &BTreeMap<String, String>) {
+    if entries.is_empty() {
+        return;
+    }
+    let Some(doc) = doc.as_mapping_mut() else {
+        return;
+    };
+    let metadata = doc
+        .entry(Value::from("metadata"))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    let Some(metadata) = metadata.as_mapping_mut() else {
+        return;
+    };
+    let target = metadata
+        .entry(Value::from(field))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+    if target.is_null() {
+        *target = Value::Mapping(Mapping::new());
+    }
+    if let Some(target) = target.as_mapping_mut() {
+        for (key, value) in entries {
+            if is_system_key(key) {
+                target.insert(Value::from(key.as_str()), Value::from(value.as_str()));
+            } else {
+                target
+                    .entry(Value::from(key.as_str()))
+                    .or_insert_with(|| Value::from(value.as_str()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn prefixes_user_keys() {
+        assert_eq!(user_metadata_key("team").unwrap(), "user.moonscale/team");
+        assert_eq!(
+            user_metadata_key("example.com/team").unwrap(),
+            "example.com/team"
+        );
+        assert_eq!(
+            user_metadata_key("user.moonscale/team").unwrap(),
+            "user.moonscale/team"
+        );
+    }
+
+    #[test]
+    fn rejects_reserved_prefixes() {
+        for key in [
+            "moonscale/owner",
+            "janitor/ttl",
+            "kubernetes.io/name",
+            "app.kubernetes.io/instance",
+            "k8s.io/name",
+            "sub.moonscale/team",
+        ] {
+            assert!(
+                user_metadata_key(key).is_err_and(|err| err.contains("reserved")),
+                "{}",
+                key
+            );
+        }
+        // Only whole domain components are reserved
+        assert!(user_metadata_key("notmoonscale/team").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        for key in [
+            "",
+            "-team",
+            "team-",
+            "Example.com/team",
+            "a/b/c",
+            "/team",
+            "team/",
+        ] {
+            assert!(user_metadata_key(key).is_err(), "{:?}", key);
+        }
+        assert!(user_metadata_key(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn validates_label_values() {
+        assert!(is_valid_label_value(""));
+        assert!(is_valid_label_value("a-b_c.d"));
+        assert!(!is_valid_label_value("-a"));
+        assert!(!is_valid_label_value("a b"));
+        assert!(!is_valid_label_value(&"a".repeat(64)));
+        assert!(!is_valid_label_name(""));
+    }
+
+    #[test]
+    fn validates_user_metadata() {
+        let metadata =
+            InstanceMetadata::from_user(&labels(&[("team", "db")]), &BTreeMap::new()).unwrap();
+
+        assert_eq!(metadata.labels, labels(&[("user.moonscale/team", "db")]));
+        assert!(
+            InstanceMetadata::from_user(&labels(&[("team", "not valid")]), &BTreeMap::new())
+                .is_err()
+        );
+        assert!(InstanceMetadata::from_user(
+            &BTreeMap::new(),
+            &labels(&[("note", &"a".repeat(MAX_ANNOTATIONS_SIZE))])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn system_entries_win_over_the_template() {
+        let metadata = InstanceMetadata::from_user(&labels(&[("team", "db")]), &BTreeMap::new())
+            .unwrap()
+            .with_system_metadata("test", "alice", "default", ResourcePreset::S, 60);
+        let mut doc: Value = serde_yaml::from_str(
+            "metadata:\n  labels:\n    moonscale/owner: bob\n    user.moonscale/team: web\n",
+        )
+        .unwrap();
+
+        metadata.inject(&mut doc);
+        let labels = &doc["metadata"]["labels"];
+
+        assert_eq!(labels["moonscale/owner"].as_str(), Some("alice"));
+        assert_eq!(labels["app.kubernetes.io/instance"].as_str(), Some("test"));
+        assert_eq!(labels["user.moonscale/team"].as_str(), Some("web"));
+        assert_eq!(
+            doc["metadata"]["annotations"]["janitor/ttl"].as_str(),
+            Some("60m")
+        );
+    }
+
+    #[test]
+    fn reads_back_user_entries() {
+        let metadata = ObjectMeta {
+            labels: Some(labels(&[
+                ("app.kubernetes.io/instance", "test"),
+                ("moonscale/owner", "alice"),
+                ("user.moonscale/team", "db"),
+                ("example.com/team", "db"),
+                ("app", "mysql"),
+            ])),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            InstanceMetadata::from_object(&metadata).labels,
+            labels(&[("example.com/team", "db"), ("user.moonscale/team", "db")])
+        );
+    }
+}
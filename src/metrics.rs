@@ -0,0 +1,136 @@
+use std::{sync::OnceLock, time::Instant};
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub database_creates: IntCounterVec,
+    pub database_deletes: IntCounterVec,
+    pub managed_instances: IntGaugeVec,
+    pub time_to_ready: Histogram,
+    pub apply_errors: IntCounterVec,
+    pub kubernetes_api_duration: HistogramVec,
+}
+
+/// Process wide metrics, registered on first use.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("moonscale".to_owned()), None)
+            .expect("Failed to create metrics registry");
+        let metrics = Metrics {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled, by route"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "HTTP request latency, by route",
+                ),
+                &["method", "route"],
+            )
+            .unwrap(),
+            database_creates: IntCounterVec::new(
+                Opts::new("database_creates_total", "Database creations, by outcome"),
+                &["outcome"],
+            )
+            .unwrap(),
+            database_deletes: IntCounterVec::new(
+                Opts::new("database_deletes_total", "Database deletions, by outcome"),
+                &["outcome"],
+            )
+            .unwrap(),
+            managed_instances: IntGaugeVec::new(
+                Opts::new("managed_instances", "Managed database instances, by phase"),
+                &["phase"],
+            )
+            .unwrap(),
+            time_to_ready: Histogram::with_opts(
+                HistogramOpts::new(
+                    "database_time_to_ready_seconds",
+                    "Time between a database creation and its StatefulSet becoming ready",
+                )
+                .buckets(vec![
+                    10.0, 20.0, 30.0, 45.0, 60.0, 90.0, 120.0, 180.0, 300.0, 600.0, 900.0,
+                ]),
+            )
+            .unwrap(),
+            apply_errors: IntCounterVec::new(
+                Opts::new("apply_errors_total", "Failed document applies, by kind"),
+                &["kind"],
+            )
+            .unwrap(),
+            kubernetes_api_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "kubernetes_api_call_duration_seconds",
+                    "Kubernetes API call latency, by verb and kind",
+                ),
+                &["verb", "kind"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        metrics
+            .registry
+            .register(Box::new(metrics.http_requests.clone()))
+            .unwrap();
+        metrics
+            .registry
+            .register(Box::new(metrics.http_request_duration.clone()))
+            .unwrap();
+        metrics
+            .registry
+            .register(Box::new(metrics.database_creates.clone()))
+            .unwrap();
+        metrics
+            .registry
+            .register(Box::new(metrics.database_deletes.clone()))
+            .unwrap();
+        metrics
+            .registry
+            .register(Box::new(metrics.managed_instances.clone()))
+            .unwrap();
+        metrics
+            .registry
+            .register(Box::new(metrics.time_to_ready.clone()))
+            .unwrap();
+        metrics
+            .registry
+            .register(Box::new(metrics.apply_errors.clone()))
+            .unwrap();
+        metrics
+            .registry
+            .register(Box::new(metrics.kubernetes_api_duration.clone()))
+            .unwrap();
+        metrics
+    }
+
+    pub fn observe_kubernetes_call(&self, verb: &str, kind: &str, started: Instant) {
+        self.kubernetes_api_duration
+            .with_label_values(&[verb, kind])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Render every registered metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            log::error!("Failed to encode metrics: {}", err);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
@@ -11,7 +11,19 @@ use rocket_okapi::{
 
 use crate::context::Context;
 
-pub struct ApiKey(String);
+pub struct ApiKey {
+    /// The name of the principal owning the key, used for auditing and ownership.
+    pub principal: String,
+    /// Whether the principal can see and manage instances it doesn't own.
+    pub admin: bool,
+}
+
+impl ApiKey {
+    /// Whether the principal can access an instance owned by `owner`.
+    pub fn can_access(&self, owner: Option<&str>) -> bool {
+        self.admin || owner == Some(self.principal.as_str())
+    }
+}
 
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for ApiKey {
@@ -22,10 +34,16 @@ impl<'r> FromRequest<'r> for ApiKey {
 
         match request.headers().get_one("Authorization") {
             None => Outcome::Error((Status::BadRequest, ())),
-            Some(key) if key == format!("Bearer {}", context.config.api_key) => {
-                Outcome::Success(ApiKey(key.to_owned()))
-            }
-            Some(_) => Outcome::Error((Status::Unauthorized, ())),
+            Some(header) => match header
+                .strip_prefix("Bearer ")
+                .and_then(|key| context.config.api_keys.get(key))
+            {
+                Some(principal) => Outcome::Success(ApiKey {
+                    principal: principal.clone(),
+                    admin: context.config.admin_principals.contains(principal),
+                }),
+                None => Outcome::Error((Status::Unauthorized, ())),
+            },
         }
     }
 }
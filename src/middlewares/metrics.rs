@@ -0,0 +1,47 @@
+use std::time::Instant;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+
+use crate::metrics::metrics;
+
+/// Time at which the request was received, stored in the request local cache.
+struct RequestStart(Instant);
+
+/// Fairing recording the count and latency of every request, by route.
+pub struct RequestMetrics;
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let started = request.local_cache(|| RequestStart(Instant::now()));
+        // Use the route template rather than the path to keep the cardinality bounded
+        let route = request
+            .route()
+            .map(|route| route.uri.to_string())
+            .unwrap_or("unmatched".to_owned());
+        let method = request.method().as_str();
+
+        metrics()
+            .http_requests
+            .with_label_values(&[method, &route, &response.status().code.to_string()])
+            .inc();
+        metrics()
+            .http_request_duration
+            .with_label_values(&[method, &route])
+            .observe(started.0.elapsed().as_secs_f64());
+    }
+}
@@ -1 +1,3 @@
 pub mod authentication;
+pub mod metrics;
+pub mod tracing;
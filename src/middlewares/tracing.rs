@@ -0,0 +1,126 @@
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{SpanKind, Status as SpanStatus, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, HeaderMap},
+    request::{self, FromRequest, Outcome},
+    Data, Request, Response,
+};
+use rocket_okapi::{
+    gen::OpenApiGenerator,
+    request::{OpenApiFromRequest, RequestHeaderInput},
+};
+
+use crate::telemetry::{tracer, RequestInfo};
+
+/// Reads the W3C `traceparent` and `tracestate` headers of an incoming request.
+struct HeaderExtractor<'a, 'r>(&'a HeaderMap<'r>);
+
+impl Extractor for HeaderExtractor<'_, '_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get_one(key)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        ["traceparent", "tracestate"]
+            .into_iter()
+            .filter(|key| self.0.contains(*key))
+            .collect()
+    }
+}
+
+/// The context holding the server span of the request, stored in the request
+/// local cache by the `RequestTracing` fairing.
+#[derive(Clone)]
+pub struct RequestTrace(Context);
+
+impl RequestTrace {
+    pub fn context(&self) -> Context {
+        self.0.clone()
+    }
+}
+
+/// Fairing opening a server span for every request, as a child of the incoming
+/// `traceparent` when there is one. The request id is taken from `X-Request-Id`
+/// when the caller sets it, and echoed back in the response.
+pub struct RequestTracing;
+
+#[rocket::async_trait]
+impl Fairing for RequestTracing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request tracing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let parent_context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        });
+        let tracer = tracer();
+        let span = tracer
+            .span_builder(format!("{} {}", request.method(), request.uri().path()))
+            .with_kind(SpanKind::Server)
+            .with_attributes(vec![
+                KeyValue::new("http.method", request.method().as_str()),
+                KeyValue::new("http.target", request.uri().to_string()),
+            ])
+            .start_with_context(&tracer, &parent_context);
+        let request_id = request
+            .headers()
+            .get_one("X-Request-Id")
+            .map(str::to_owned)
+            .unwrap_or_else(|| Alphanumeric.sample_string(&mut rand::thread_rng(), 16));
+        let request_context = parent_context.with_span(span).with_value(RequestInfo {
+            request_id,
+            route: format!("{} {}", request.method(), request.uri().path()),
+        });
+
+        request.local_cache(|| RequestTrace(request_context));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let request_context = request.local_cache(|| RequestTrace(Context::new()));
+        let span = request_context.0.span();
+        let status = response.status();
+
+        // The route is only known once the request has been routed
+        if let Some(route) = request.route() {
+            span.update_name(format!("{} {}", request.method(), route.uri));
+            span.set_attribute(KeyValue::new("http.route", route.uri.to_string()));
+        }
+        span.set_attribute(KeyValue::new("http.status_code", status.code as i64));
+        if status.class().is_server_error() {
+            span.set_status(SpanStatus::error(status.to_string()));
+        }
+        span.end();
+        if let Some(request_info) = request_context.0.get::<RequestInfo>() {
+            response.set_header(Header::new("X-Request-Id", request_info.request_id.clone()));
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestTrace {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(request.local_cache(|| RequestTrace(Context::new())).clone())
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for RequestTrace {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
@@ -0,0 +1,71 @@
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Delete,
+    Resize,
+    Pause,
+    Resume,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Delete => "delete",
+            AuditAction::Resize => "resize",
+            AuditAction::Pause => "pause",
+            AuditAction::Resume => "resume",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecordModel {
+    /// RFC 3339 timestamp of the moment the call completed.
+    pub timestamp: String,
+
+    /// The name of the API key that performed the call.
+    pub principal: String,
+
+    /// The kind of mutation that was requested.
+    pub action: AuditAction,
+
+    /// The HTTP method and route that was called, eg: `DELETE /api/database/<instance>`.
+    pub route: String,
+
+    /// The moonscale instance targeted by the call.
+    pub instance: String,
+
+    /// Whether the call succeeded or not.
+    pub outcome: AuditOutcome,
+
+    /// The HTTP status code returned to the caller.
+    pub status: u16,
+
+    /// How long the call took, in milliseconds.
+    pub duration_ms: u64,
+}
+
+pub type ListAuditResponseModel = Vec<AuditRecordModel>;
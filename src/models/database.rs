@@ -1,18 +1,79 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::serde_json::Value;
+use rocket::FromForm;
 use rocket_okapi::okapi::schemars;
 use rocket_okapi::okapi::schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Sizes used to be a number of GB, keep accepting them along with quantities.
+fn deserialize_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Gigabytes(u64),
+        Quantity(String),
+    }
+
+    Ok(
+        Option::<Size>::deserialize(deserializer)?.map(|size| match size {
+            Size::Gigabytes(gigabytes) => format!("{}Gi", gigabytes),
+            Size::Quantity(quantity) => quantity,
+        }),
+    )
+}
 
 #[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateDatabaseRequestModel {
-    // TODO: Set constraints for the name
     /// Required: The name of the database, usually just the ID of the pull requested
-    /// associated to this database
+    /// associated to this database. At most 35 lowercase alphanumeric characters or
+    /// `-`, starting and ending with an alphanumeric character.
     pub name: String,
 
-    /// Required: The size of the database in GB, this will always be clamped between
-    /// 1GB and 5GB
-    pub size: usize,
+    /// Optional: The storage of the database as a Kubernetes quantity, eg: `500Mi`
+    /// or `2Gi`. It must fit the bounds of the profile, which also sets the default.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub size: Option<String>,
+
+    /// Optional: The profile of the database, `default` when unset.
+    #[serde(default)]
+    pub profile: Option<String>,
+
+    /// Optional: The CPU and memory preset of the database, `xs`, `s`, `m` or `l`.
+    /// Defaults to the preset of the profile.
+    #[serde(default)]
+    pub preset: Option<ResourcePreset>,
+
+    /// Optional: Labels added to every resource of the database. Keys without a
+    /// prefix are prefixed with `user.moonscale/`.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    /// Optional: Annotations added to every resource of the database. Keys without
+    /// a prefix are prefixed with `user.moonscale/`.
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+
+    /// Optional: How long the database is kept before being deleted, in minutes.
+    /// Defaults to the server's resource TTL.
+    #[serde(default)]
+    pub ttl: Option<usize>,
+
+    /// Optional: Pause the database after this many minutes without connections.
+    /// Defaults to the server's auto-pause delay, `0` disables it.
+    #[serde(default)]
+    pub auto_pause: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResizeDatabaseRequestModel {
+    /// Required: The new storage of the database as a Kubernetes quantity, eg: `4Gi`.
+    /// Volumes can only grow, within the bounds of the profile of the database.
+    pub size: String,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema)]
@@ -29,10 +90,269 @@ pub struct DatabaseInstanceModel {
 
     /// The database name
     pub database_name: String,
+
+    /// The user labels of the database.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+
+    /// The user annotations of the database.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
     // A timestamp corresponding to the approximate time where the database will be deleted.
     //pub deletion_timestamp: OffsetDateTime,
 }
 
-pub type CreateDatabaseResponseModel = DatabaseInstanceModel;
+impl DatabaseInstanceModel {
+    pub fn new(instance_name: &str, root_password: String, planetscale_api_url: String) -> Self {
+        DatabaseInstanceModel {
+            planetscale_api_url,
+            database_username: "root".to_owned(),
+            database_password: root_password,
+            database_name: instance_name.to_owned(),
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+        }
+    }
+}
+
+/// A t-shirt size mapped to the CPU and memory of the database containers, and to
+/// the MySQL settings that depend on them.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourcePreset {
+    Xs,
+    S,
+    M,
+    L,
+}
+
+impl ResourcePreset {
+    pub const ALL: [ResourcePreset; 4] = [
+        ResourcePreset::Xs,
+        ResourcePreset::S,
+        ResourcePreset::M,
+        ResourcePreset::L,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResourcePreset::Xs => "xs",
+            ResourcePreset::S => "s",
+            ResourcePreset::M => "m",
+            ResourcePreset::L => "l",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ResourcePreset> {
+        ResourcePreset::ALL
+            .into_iter()
+            .find(|preset| preset.as_str() == name)
+    }
+}
+
+/// The lifecycle phase of a moonscale instance, derived from its StatefulSet.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum DatabasePhase {
+    /// The database is being created and isn't accepting connections yet.
+    Provisioning,
+    /// The database is up and accepting connections.
+    Ready,
+    /// The database is scaled down, its volumes and credentials are kept.
+    Paused,
+    /// The database is being deleted.
+    Terminating,
+}
+
+impl DatabasePhase {
+    pub const ALL: [DatabasePhase; 4] = [
+        DatabasePhase::Provisioning,
+        DatabasePhase::Ready,
+        DatabasePhase::Paused,
+        DatabasePhase::Terminating,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DatabasePhase::Provisioning => "Provisioning",
+            DatabasePhase::Ready => "Ready",
+            DatabasePhase::Paused => "Paused",
+            DatabasePhase::Terminating => "Terminating",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateDatabaseResponseModel {
+    #[serde(flatten)]
+    pub instance: DatabaseInstanceModel,
+
+    /// The operation tracking the provisioning, it completes once the database is
+    /// ready. Poll `GET /api/operations/<id>` to follow it.
+    pub operation_id: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceResourceModel {
+    /// The kind of the resource, eg: `StatefulSet`.
+    pub kind: String,
+
+    pub name: String,
+
+    /// Whether the resource currently exists in the cluster.
+    pub present: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseDetailsModel {
+    #[serde(flatten)]
+    pub instance: DatabaseInstanceModel,
+
+    pub phase: DatabasePhase,
+
+    /// The principal owning the instance, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    /// RFC 3339 timestamp of when the instance was created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+
+    /// RFC 3339 timestamp of when the instance will be deleted by the janitor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+
+    /// RFC 3339 timestamp of when the instance was paused, while it is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused_at: Option<String>,
+
+    /// The storage requested for the database, eg: `1Gi`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+
+    /// The profile the instance was created with.
+    pub profile: String,
+
+    /// The CPU and memory preset of the instance, unknown for instances created
+    /// before presets existed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<ResourcePreset>,
+
+    /// Every resource the template produces for the instance.
+    pub resources: Vec<InstanceResourceModel>,
+}
+
+pub type GetDatabaseResponseModel = DatabaseDetailsModel;
+
+/// Query parameters of `GET /api/database`. Label based filters (`owner`, `label`)
+/// are applied by Kubernetes, the others are applied to each page, so a page may
+/// hold fewer than `limit` items while `continue` is still set.
+#[derive(FromForm, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ListDatabaseQueryModel {
+    /// The maximum number of instances fetched for this page.
+    pub limit: Option<u32>,
+
+    /// The `continue` token returned by the previous page.
+    #[field(name = "continue")]
+    #[serde(rename = "continue")]
+    pub continue_token: Option<String>,
+
+    /// Only return instances whose name starts with this prefix.
+    pub prefix: Option<String>,
+
+    /// Only return instances owned by this principal.
+    pub owner: Option<String>,
+
+    /// Only return instances matching this label selector, eg: `team=backend`.
+    pub label: Option<String>,
+
+    /// Only return instances in this phase, eg: `Ready`.
+    pub phase: Option<String>,
+
+    /// Only return instances expiring before this RFC 3339 timestamp.
+    #[field(name = "expiringBefore")]
+    pub expiring_before: Option<String>,
 
-pub type ListDatabaseResponseModel = Vec<DatabaseInstanceModel>;
+    /// Sort by `name`, `createdAt` or `expiresAt`, prefix with `-` to sort in
+    /// descending order. Can't be combined with `limit` or `continue`.
+    pub sort: Option<String>,
+
+    /// Include the credentials of each instance, they are left out by default.
+    /// Only admin keys can include them.
+    #[field(name = "includeCredentials")]
+    pub include_credentials: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseSummaryModel {
+    /// The database name
+    pub database_name: String,
+
+    /// The URL to the PlanetScale API pointing to the underlying database.
+    pub planetscale_api_url: String,
+
+    /// The username to access the database.
+    pub database_username: String,
+
+    /// The password to access the database, only set with `includeCredentials=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_password: Option<String>,
+
+    pub phase: DatabasePhase,
+
+    /// The principal owning the instance, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    /// RFC 3339 timestamp of when the instance was created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+
+    /// RFC 3339 timestamp of when the instance will be deleted by the janitor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+
+    /// The user labels of the database.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ListDatabaseResponseModel {
+    pub items: Vec<DatabaseSummaryModel>,
+
+    /// Pass it back as `continue` to fetch the next page, unset on the last page.
+    #[serde(rename = "continue", skip_serializing_if = "Option::is_none")]
+    pub continue_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunErrorModel {
+    /// The kind of the manifest the error relates to, unset for template errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+
+    /// The name of the manifest the error relates to, unset for template errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunDatabaseResponseModel {
+    /// Whether the whole template would be applied successfully.
+    pub valid: bool,
+
+    /// The manifests rendered for the database, as they would be applied.
+    pub manifests: Vec<Value>,
+
+    pub errors: Vec<DryRunErrorModel>,
+}
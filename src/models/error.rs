@@ -0,0 +1,27 @@
+use rocket::{http::Status, serde::json::Json};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorModel {
+    /// A stable identifier of the error, eg: `owner_instance_quota_exceeded`.
+    pub error: String,
+
+    /// A human readable explanation of the error.
+    pub message: String,
+}
+
+/// An error status along with a body explaining it.
+pub type ErrorResponse = (Status, Json<ErrorModel>);
+
+pub fn error_response(status: Status, error: &str, message: impl Into<String>) -> ErrorResponse {
+    (
+        status,
+        Json(ErrorModel {
+            error: error.to_owned(),
+            message: message.into(),
+        }),
+    )
+}
@@ -0,0 +1,34 @@
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthCheckModel {
+    /// The name of the check, eg: `kubernetes-api`.
+    pub name: String,
+
+    /// Whether the check passed.
+    pub healthy: bool,
+
+    /// Details on why the check failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// How long the check took, in milliseconds.
+    pub duration_ms: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReportModel {
+    /// Whether every check passed.
+    pub healthy: bool,
+
+    /// RFC 3339 timestamp of when the checks were run, results are cached for a few seconds.
+    pub checked_at: String,
+
+    /// The result of each individual check, only returned in verbose mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checks: Option<Vec<HealthCheckModel>>,
+}
@@ -0,0 +1,32 @@
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegrationAction {
+    /// A database was created for the pull request.
+    Created,
+    /// The database of the pull request is being deleted.
+    Deleted,
+    /// The event doesn't require any change.
+    Ignored,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrationResponseModel {
+    pub action: IntegrationAction,
+
+    /// The instance of the pull request, when the event is about one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+
+    /// The operation tracking the change, if one was started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operation_id: Option<String>,
+
+    /// Why the event was ignored, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
@@ -0,0 +1,56 @@
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum LifecycleEventType {
+    /// A new instance appeared.
+    Created,
+    /// The instance is accepting connections.
+    Ready,
+    /// The instance didn't become ready in time.
+    Failed,
+    /// The instance was scaled down, its data is kept until it's resumed.
+    Paused,
+    /// The instance will be deleted by the janitor soon.
+    ExpiringSoon,
+    /// The instance is gone.
+    Deleted,
+}
+
+impl LifecycleEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LifecycleEventType::Created => "created",
+            LifecycleEventType::Ready => "ready",
+            LifecycleEventType::Failed => "failed",
+            LifecycleEventType::Paused => "paused",
+            LifecycleEventType::ExpiringSoon => "expiring-soon",
+            LifecycleEventType::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LifecycleEventModel {
+    /// Increasing identifier of the event, send it back as `Last-Event-ID` to resume.
+    pub id: u64,
+
+    pub event: LifecycleEventType,
+
+    /// The moonscale instance the event is about.
+    pub instance: String,
+
+    /// The principal owning the instance, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    /// RFC 3339 timestamp of when the change was observed.
+    pub timestamp: String,
+
+    /// RFC 3339 timestamp of when the instance expires, if it has a TTL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
@@ -1 +1,9 @@
+pub mod audit;
 pub mod database;
+pub mod error;
+pub mod health;
+pub mod integration;
+pub mod lifecycle;
+pub mod operation;
+pub mod quota;
+pub mod webhook;
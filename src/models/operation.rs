@@ -0,0 +1,117 @@
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationType {
+    Create,
+    Delete,
+    Resize,
+    Pause,
+    Resume,
+}
+
+impl OperationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationType::Create => "create",
+            OperationType::Delete => "delete",
+            OperationType::Resize => "resize",
+            OperationType::Pause => "pause",
+            OperationType::Resume => "resume",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum OperationState {
+    /// The operation is in progress.
+    Running,
+    /// The operation completed successfully.
+    Succeeded,
+    /// The operation completed, but some steps failed. See `error` and `resources`.
+    Failed,
+}
+
+impl OperationState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationState::Running => "Running",
+            OperationState::Succeeded => "Succeeded",
+            OperationState::Failed => "Failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum ResourceState {
+    /// A delete order was sent, we are waiting for the resource to disappear.
+    Deleting,
+    /// The resource is gone.
+    Deleted,
+    /// The resource couldn't be applied, deleted, resized or scaled, eg: it's stuck on a
+    /// finalizer.
+    Failed,
+    /// A new size was requested, we are waiting for the volume to be expanded.
+    Resizing,
+    /// The volume has the requested capacity.
+    Resized,
+    /// The replicas were changed, we are waiting for the pods to follow.
+    Scaling,
+    /// The pods are stopped, or ready, as requested.
+    Scaled,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceProgressModel {
+    /// The kind of the resource, eg: `StatefulSet`.
+    pub kind: String,
+
+    /// The name of the resource.
+    pub name: String,
+
+    pub state: ResourceState,
+
+    /// How many apply, delete or resize orders were sent for this resource.
+    pub attempts: u32,
+
+    /// Why the last attempt failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationModel {
+    /// The identifier of the operation, used to poll `GET /api/operations/<id>`.
+    pub id: String,
+
+    #[serde(rename = "type")]
+    pub operation_type: OperationType,
+
+    /// The moonscale instance targeted by the operation.
+    pub instance: String,
+
+    /// The name of the API key that started the operation.
+    pub principal: String,
+
+    pub state: OperationState,
+
+    /// RFC 3339 timestamp of when the operation was started.
+    pub created_at: String,
+
+    /// RFC 3339 timestamp of when the operation completed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+
+    /// Progress of each resource handled by the operation.
+    pub resources: Vec<ResourceProgressModel>,
+
+    /// Why the operation failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub type ListOperationsResponseModel = Vec<OperationModel>;
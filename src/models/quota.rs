@@ -0,0 +1,46 @@
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The configured limits, unset ones aren't enforced.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaLimitsModel {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_instances: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_instances_per_owner: Option<usize>,
+
+    /// Maximum storage of every instance together, eg: `50Gi`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_storage: Option<String>,
+
+    /// Maximum time to live of an instance, in minutes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ttl: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaUsageModel {
+    /// Instances of every owner.
+    pub instances: usize,
+
+    /// Instances owned by the caller.
+    pub owner_instances: usize,
+
+    /// Storage requested by every instance together, eg: `12Gi`.
+    pub storage: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaModel {
+    /// The principal the owner usage is reported for.
+    pub principal: String,
+
+    pub limits: QuotaLimitsModel,
+
+    pub usage: QuotaUsageModel,
+}
@@ -0,0 +1,74 @@
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::lifecycle::LifecycleEventType;
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWebhookRequestModel {
+    /// Required: The URL receiving the `POST` notifications, `http` or `https`.
+    pub url: String,
+
+    /// Required: The secret used to sign the payloads, the signature is sent in the
+    /// `X-Moonscale-Signature` header as `sha256=<hex HMAC of the body>`.
+    pub secret: String,
+
+    /// The lifecycle events to be notified of, defaults to `ready`, `failed`,
+    /// `expiring-soon` and `deleted`.
+    pub events: Option<Vec<LifecycleEventType>>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookModel {
+    /// The identifier of the webhook.
+    pub id: String,
+
+    pub url: String,
+
+    pub events: Vec<LifecycleEventType>,
+
+    /// The name of the API key that registered the webhook, it's only notified of the
+    /// instances this principal can access.
+    pub principal: String,
+
+    /// RFC 3339 timestamp of when the webhook was registered.
+    pub created_at: String,
+}
+
+pub type ListWebhooksResponseModel = Vec<WebhookModel>;
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDeliveryModel {
+    /// The identifier of the delivery, sent in the `X-Moonscale-Delivery` header and
+    /// shared by every attempt.
+    pub id: String,
+
+    /// The id of the lifecycle event being delivered.
+    pub event_id: u64,
+
+    pub event: LifecycleEventType,
+
+    pub instance: String,
+
+    /// The attempt number, starting at 1.
+    pub attempt: u32,
+
+    /// Whether the receiver answered with a 2xx status.
+    pub success: bool,
+
+    /// The HTTP status returned by the receiver, if it answered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+
+    /// Why the attempt failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// RFC 3339 timestamp of the attempt.
+    pub attempted_at: String,
+}
+
+pub type ListWebhookDeliveriesResponseModel = Vec<WebhookDeliveryModel>;
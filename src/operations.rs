@@ -0,0 +1,350 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use k8s_openapi::{api::core::v1::ConfigMap, serde_json};
+use kube::{
+    api::{DeleteParams, ListParams, ObjectMeta, Patch, PatchParams},
+    Api, Client,
+};
+use log::{error, info, warn};
+use rand::distributions::{Alphanumeric, DistString};
+use rocket::tokio::{
+    self,
+    sync::{mpsc, Notify},
+};
+
+use crate::metrics::metrics;
+use crate::models::operation::{OperationModel, OperationState, OperationType};
+
+const OPERATION_LABELS: &str =
+    "app.kubernetes.io/managed-by=Moonscale,app.kubernetes.io/component=operation";
+const OPERATION_DATA_KEY: &str = "operation.json";
+
+/// Tracks the long running operations executed in the background. Every change is
+/// persisted to a ConfigMap so operations survive a restart of the server.
+pub struct OperationStore {
+    operations: Arc<Mutex<HashMap<String, OperationModel>>>,
+    /// Ids of the operations that changed and must be written to Kubernetes.
+    persist: mpsc::UnboundedSender<String>,
+    /// Woken up on every change, for long-polling callers.
+    changed: Notify,
+    /// How long finished operations are kept before being pruned.
+    retention: Duration,
+}
+
+/// Filters applied when listing operations, `None` matches everything.
+pub struct OperationFilter<'a> {
+    pub principal: Option<&'a str>,
+    pub instance: Option<&'a str>,
+    pub operation_type: Option<&'a str>,
+    pub state: Option<&'a str>,
+    pub limit: Option<usize>,
+}
+
+fn now_rfc3339() -> String {
+    humantime::format_rfc3339(SystemTime::now()).to_string()
+}
+
+fn configmap_name(id: &str) -> String {
+    format!("moonscale-operation-{}", id)
+}
+
+fn operation_configmap(operation: &OperationModel) -> Result<ConfigMap, serde_json::Error> {
+    let labels = OPERATION_LABELS
+        .split(',')
+        .filter_map(|label| label.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+
+    Ok(ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(configmap_name(&operation.id)),
+            labels: Some(labels),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            OPERATION_DATA_KEY.to_owned(),
+            serde_json::to_string(operation)?,
+        )])),
+        ..Default::default()
+    })
+}
+
+/// Write the latest state of the operation, or delete its ConfigMap once it's pruned.
+async fn persist_operation(
+    api: &Api<ConfigMap>,
+    operations: &Mutex<HashMap<String, OperationModel>>,
+    id: &str,
+) {
+    let operation = operations.lock().unwrap().get(id).cloned();
+    let started = Instant::now();
+
+    match operation {
+        Some(operation) => {
+            let configmap = match operation_configmap(&operation) {
+                Ok(configmap) => configmap,
+                Err(err) => {
+                    error!("Failed to serialize operation {}: {}", id, err);
+                    return;
+                }
+            };
+            let result = api
+                .patch(
+                    &configmap_name(id),
+                    &PatchParams::apply("moonscale").force(),
+                    &Patch::Apply(configmap),
+                )
+                .await;
+
+            metrics().observe_kubernetes_call("apply", "ConfigMap", started);
+            if let Err(err) = result {
+                warn!("Failed to persist operation {}: {}", id, err);
+            }
+        }
+        None => {
+            let result = api
+                .delete(&configmap_name(id), &DeleteParams::default())
+                .await;
+
+            metrics().observe_kubernetes_call("delete", "ConfigMap", started);
+            match result {
+                Ok(_) => {}
+                Err(kube::Error::Api(response)) if response.code == 404 => {}
+                Err(err) => warn!("Failed to delete pruned operation {}: {}", id, err),
+            }
+        }
+    }
+}
+
+/// Persist changed operations one at a time so writes can't be reordered, coalescing
+/// the changes that piled up while the previous write was in flight.
+async fn run_persister(
+    kubeclient: Client,
+    operations: Arc<Mutex<HashMap<String, OperationModel>>>,
+    mut changes: mpsc::UnboundedReceiver<String>,
+) {
+    let api: Api<ConfigMap> = Api::default_namespaced(kubeclient);
+
+    while let Some(id) = changes.recv().await {
+        let mut ids = HashSet::from([id]);
+
+        while let Ok(id) = changes.try_recv() {
+            ids.insert(id);
+        }
+        for id in ids {
+            persist_operation(&api, &operations, &id).await;
+        }
+    }
+}
+
+/// Read back the operations persisted by a previous run of the server.
+async fn load_operations(kubeclient: &Client) -> Result<Vec<OperationModel>, kube::Error> {
+    let api: Api<ConfigMap> = Api::default_namespaced(kubeclient.clone());
+    let started = Instant::now();
+    let configmaps = api
+        .list(&ListParams::default().labels(OPERATION_LABELS))
+        .await;
+
+    metrics().observe_kubernetes_call("list", "ConfigMap", started);
+
+    Ok(configmaps?
+        .items
+        .into_iter()
+        .filter_map(|configmap| {
+            let data = configmap.data?.remove(OPERATION_DATA_KEY)?;
+
+            serde_json::from_str(&data)
+                .map_err(|err| {
+                    warn!(
+                        "Ignoring invalid operation ConfigMap {}: {}",
+                        configmap.metadata.name.unwrap_or_default(),
+                        err
+                    )
+                })
+                .ok()
+        })
+        .collect())
+}
+
+impl OperationStore {
+    /// Restore the persisted operations and start persisting changes. Operations
+    /// that were still running were interrupted by the restart and are marked failed.
+    pub async fn load(kubeclient: &Client, retention: Duration) -> OperationStore {
+        let (persist, changes) = mpsc::unbounded_channel();
+        let store = OperationStore {
+            operations: Arc::new(Mutex::new(HashMap::new())),
+            persist,
+            changed: Notify::new(),
+            retention,
+        };
+        let restored = load_operations(kubeclient).await.unwrap_or_else(|err| {
+            error!("Failed to restore persisted operations: {}", err);
+            vec![]
+        });
+
+        info!("Restored {} persisted operations", restored.len());
+        store.operations.lock().unwrap().extend(
+            restored
+                .into_iter()
+                .map(|operation| (operation.id.clone(), operation)),
+        );
+        let interrupted: Vec<String> = store
+            .list(&OperationFilter {
+                principal: None,
+                instance: None,
+                operation_type: None,
+                state: Some(OperationState::Running.as_str()),
+                limit: None,
+            })
+            .into_iter()
+            .map(|operation| operation.id)
+            .collect();
+
+        for id in interrupted {
+            warn!("Operation {} was interrupted by a restart", id);
+            store.finish(
+                &id,
+                Some("Interrupted by a restart of the server".to_owned()),
+            );
+        }
+        store.prune();
+        tokio::spawn(run_persister(
+            kubeclient.clone(),
+            store.operations.clone(),
+            changes,
+        ));
+        store
+    }
+
+    fn notify_change(&self, id: &str) {
+        let _ = self.persist.send(id.to_owned());
+        self.changed.notify_waiters();
+    }
+
+    /// Drop the operations that finished longer than the retention ago.
+    fn prune(&self) {
+        let now = SystemTime::now();
+        let mut operations = self.operations.lock().unwrap();
+        let expired: Vec<String> = operations
+            .values()
+            .filter(|operation| {
+                operation
+                    .finished_at
+                    .as_deref()
+                    .and_then(|finished_at| humantime::parse_rfc3339_weak(finished_at).ok())
+                    .and_then(|finished_at| now.duration_since(finished_at).ok())
+                    .is_some_and(|age| age > self.retention)
+            })
+            .map(|operation| operation.id.clone())
+            .collect();
+
+        for id in expired {
+            operations.remove(&id);
+            let _ = self.persist.send(id);
+        }
+    }
+
+    pub fn create(
+        &self,
+        operation_type: OperationType,
+        instance: &str,
+        principal: &str,
+    ) -> OperationModel {
+        let operation = OperationModel {
+            id: Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 16)
+                .to_lowercase(),
+            operation_type,
+            instance: instance.to_owned(),
+            principal: principal.to_owned(),
+            state: OperationState::Running,
+            created_at: now_rfc3339(),
+            finished_at: None,
+            resources: vec![],
+            error: None,
+        };
+
+        self.prune();
+        self.operations
+            .lock()
+            .unwrap()
+            .insert(operation.id.clone(), operation.clone());
+        self.notify_change(&operation.id);
+        operation
+    }
+
+    pub fn get(&self, id: &str) -> Option<OperationModel> {
+        self.operations.lock().unwrap().get(id).cloned()
+    }
+
+    /// Operations matching the filter, newest first.
+    pub fn list(&self, filter: &OperationFilter) -> Vec<OperationModel> {
+        let mut operations: Vec<OperationModel> = self
+            .operations
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|operation| filter.principal.is_none_or(|p| operation.principal == p))
+            .filter(|operation| filter.instance.is_none_or(|i| operation.instance == i))
+            .filter(|operation| {
+                filter
+                    .operation_type
+                    .is_none_or(|t| operation.operation_type.as_str() == t)
+            })
+            .filter(|operation| filter.state.is_none_or(|s| operation.state.as_str() == s))
+            .cloned()
+            .collect();
+
+        // RFC 3339 timestamps with the same precision sort lexicographically
+        operations.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        operations.truncate(filter.limit.unwrap_or(operations.len()));
+        operations
+    }
+
+    /// Wait up to `timeout` for the operation to complete, returns its latest state.
+    pub async fn wait(&self, id: &str, timeout: Duration) -> Option<OperationModel> {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            // Register before checking the state so a change in between isn't missed
+            let notified = self.changed.notified();
+            let operation = self.get(id)?;
+
+            if operation.state != OperationState::Running || tokio::time::Instant::now() >= deadline
+            {
+                return Some(operation);
+            }
+            let _ = tokio::time::timeout_at(deadline, notified).await;
+        }
+    }
+
+    /// Apply `update` to the operation, if it still exists.
+    pub fn update(&self, id: &str, update: impl FnOnce(&mut OperationModel)) {
+        let updated = match self.operations.lock().unwrap().get_mut(id) {
+            Some(operation) => {
+                update(operation);
+                true
+            }
+            None => false,
+        };
+
+        if updated {
+            self.notify_change(id);
+        }
+    }
+
+    /// Mark the operation as completed, failed if `error` is set.
+    pub fn finish(&self, id: &str, error: Option<String>) {
+        self.update(id, |operation| {
+            operation.state = match error {
+                Some(_) => OperationState::Failed,
+                None => OperationState::Succeeded,
+            };
+            operation.error = error;
+            operation.finished_at = Some(now_rfc3339());
+        });
+    }
+}
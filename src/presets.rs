@@ -0,0 +1,71 @@
+use serde::Serialize;
+
+use crate::models::database::ResourcePreset;
+
+/// The preset of the instances whose profile doesn't set one.
+pub const DEFAULT_PRESET: ResourcePreset = ResourcePreset::S;
+
+/// Resources of the database containers, exposed to the template as `resources`.
+/// MySQL gets as much memory as it requests so a busy neighbour can't starve it,
+/// the buffer pool takes about half of it to leave room for the connections.
+#[derive(Serialize)]
+pub struct PresetResources {
+    pub mysql_cpu_request: &'static str,
+    pub mysql_cpu_limit: &'static str,
+    pub mysql_memory: &'static str,
+    pub proxy_cpu_request: &'static str,
+    pub proxy_cpu_limit: &'static str,
+    pub proxy_memory_request: &'static str,
+    pub proxy_memory_limit: &'static str,
+    pub innodb_buffer_pool_size: &'static str,
+    pub max_connections: u32,
+}
+
+pub fn preset_resources(preset: ResourcePreset) -> PresetResources {
+    match preset {
+        ResourcePreset::Xs => PresetResources {
+            mysql_cpu_request: "100m",
+            mysql_cpu_limit: "500m",
+            mysql_memory: "512Mi",
+            proxy_cpu_request: "50m",
+            proxy_cpu_limit: "200m",
+            proxy_memory_request: "64Mi",
+            proxy_memory_limit: "128Mi",
+            innodb_buffer_pool_size: "256M",
+            max_connections: 50,
+        },
+        ResourcePreset::S => PresetResources {
+            mysql_cpu_request: "250m",
+            mysql_cpu_limit: "1",
+            mysql_memory: "1Gi",
+            proxy_cpu_request: "100m",
+            proxy_cpu_limit: "250m",
+            proxy_memory_request: "128Mi",
+            proxy_memory_limit: "256Mi",
+            innodb_buffer_pool_size: "512M",
+            max_connections: 100,
+        },
+        ResourcePreset::M => PresetResources {
+            mysql_cpu_request: "500m",
+            mysql_cpu_limit: "2",
+            mysql_memory: "2Gi",
+            proxy_cpu_request: "250m",
+            proxy_cpu_limit: "500m",
+            proxy_memory_request: "256Mi",
+            proxy_memory_limit: "512Mi",
+            innodb_buffer_pool_size: "1280M",
+            max_connections: 200,
+        },
+        ResourcePreset::L => PresetResources {
+            mysql_cpu_request: "1",
+            mysql_cpu_limit: "4",
+            mysql_memory: "4Gi",
+            proxy_cpu_request: "250m",
+            proxy_cpu_limit: "1",
+            proxy_memory_request: "256Mi",
+            proxy_memory_limit: "512Mi",
+            innodb_buffer_pool_size: "2560M",
+            max_connections: 400,
+        },
+    }
+}
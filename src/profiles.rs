@@ -0,0 +1,137 @@
+use std::{collections::HashMap, fs};
+
+use serde::Deserialize;
+
+use crate::context::Config;
+use crate::metadata::is_valid_label_name;
+use crate::models::database::ResourcePreset;
+use crate::presets::DEFAULT_PRESET;
+use crate::quantity::{format_quantity, parse_quantity};
+
+/// The profile of the instances created without one.
+pub const DEFAULT_PROFILE: &str = "default";
+const DEFAULT_MIN_SIZE: &str = "1Gi";
+const DEFAULT_MAX_SIZE: &str = "5Gi";
+
+/// A profile as written in the `MOONSCALE_PROFILES` file, every field is optional.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileDefinition {
+    storage_class: Option<String>,
+    min_size: Option<String>,
+    max_size: Option<String>,
+    default_size: Option<String>,
+    default_preset: Option<ResourcePreset>,
+}
+
+/// Storage and resource settings applied to the instances created with the profile,
+/// sizes are in bytes.
+#[derive(Clone)]
+pub struct Profile {
+    pub name: String,
+    pub storage_class: String,
+    pub min_size: u64,
+    pub max_size: u64,
+    pub default_size: u64,
+    pub default_preset: ResourcePreset,
+}
+
+impl Profile {
+    fn from_definition(
+        name: &str,
+        definition: ProfileDefinition,
+        storage_class: &str,
+    ) -> Result<Profile, String> {
+        if !is_valid_label_name(name) {
+            return Err(format!("Invalid profile name {:?}", name));
+        }
+        let parse_size = |field: &str, size: Option<String>, default: &str| {
+            parse_quantity(size.as_deref().unwrap_or(default))
+                .map_err(|err| format!("Invalid {} of profile {}: {}", field, name, err))
+        };
+        let min_size = parse_size("minSize", definition.min_size, DEFAULT_MIN_SIZE)?;
+        let max_size = parse_size("maxSize", definition.max_size, DEFAULT_MAX_SIZE)?;
+        let default_size = match definition.default_size {
+            Some(size) => parse_size("defaultSize", Some(size), "")?,
+            None => min_size,
+        };
+
+        if !(min_size <= default_size && default_size <= max_size) {
+            return Err(format!(
+                "Profile {} must satisfy minSize <= defaultSize <= maxSize",
+                name
+            ));
+        }
+        Ok(Profile {
+            name: name.to_owned(),
+            storage_class: definition.storage_class.unwrap_or(storage_class.to_owned()),
+            min_size,
+            max_size,
+            default_size,
+            default_preset: definition.default_preset.unwrap_or(DEFAULT_PRESET),
+        })
+    }
+
+    /// The storage requested for an instance, rejecting sizes outside the bounds of
+    /// the profile rather than clamping them.
+    pub fn resolve_size(&self, size: Option<&str>) -> Result<u64, String> {
+        let Some(size) = size else {
+            return Ok(self.default_size);
+        };
+        let bytes = parse_quantity(size)?;
+
+        if bytes < self.min_size || bytes > self.max_size {
+            return Err(format!(
+                "The size {} is out of the {} to {} range of profile {}",
+                size,
+                format_quantity(self.min_size),
+                format_quantity(self.max_size),
+                self.name
+            ));
+        }
+        Ok(bytes)
+    }
+}
+
+/// Load the profiles from the YAML file mapping profile names to their settings,
+/// the `default` profile is added when the file doesn't define it.
+pub fn load_profiles(
+    path: Option<&str>,
+    storage_class: &str,
+) -> Result<HashMap<String, Profile>, String> {
+    let definitions: HashMap<String, ProfileDefinition> = match path {
+        Some(path) => {
+            let data = fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read {}: {}", path, err))?;
+
+            serde_yaml::from_str(&data)
+                .map_err(|err| format!("Failed to parse {}: {}", path, err))?
+        }
+        None => HashMap::new(),
+    };
+    let mut profiles = definitions
+        .into_iter()
+        .map(|(name, definition)| {
+            Profile::from_definition(&name, definition, storage_class)
+                .map(|profile| (name, profile))
+        })
+        .collect::<Result<HashMap<String, Profile>, String>>()?;
+
+    if !profiles.contains_key(DEFAULT_PROFILE) {
+        profiles.insert(
+            DEFAULT_PROFILE.to_owned(),
+            Profile::from_definition(DEFAULT_PROFILE, ProfileDefinition::default(), storage_class)?,
+        );
+    }
+    Ok(profiles)
+}
+
+/// The requested profile, or the default one.
+pub fn resolve_profile<'a>(config: &'a Config, name: Option<&str>) -> Result<&'a Profile, String> {
+    let name = name.unwrap_or(DEFAULT_PROFILE);
+
+    config
+        .profiles
+        .get(name)
+        .ok_or(format!("Unknown profile {}", name))
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use base64::prelude::*;
+use k8s_openapi::api::core::v1::Secret;
+use kube::Api;
+use log::error;
+use rocket::http::Status;
+
+use crate::context::Config;
+use crate::context::Context;
+use crate::crypto::constant_time_eq;
+use crate::metrics::metrics;
+use crate::models::error::{error_response, ErrorResponse};
+
+/// How long the proxy reuses the root password of an instance before reading its
+/// Secret again.
+const PASSWORD_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// How moonscale reverse-proxies the PlanetScale HTTP API of the instances, in
+/// place of an Ingress per instance.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProxyMode {
+    /// `<proxy url>/<instance>/psdb.v1alpha1.Database/...`
+    Path,
+    /// `moonscale-instance-<instance>.<domain>`, routed to moonscale by a single
+    /// wildcard Ingress.
+    Host,
+}
+
+impl ProxyMode {
+    pub fn from_name(name: &str) -> Option<ProxyMode> {
+        match name {
+            "path" => Some(ProxyMode::Path),
+            "host" => Some(ProxyMode::Host),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ProxyConfig {
+    pub mode: ProxyMode,
+    /// Public URL of moonscale, the instance URLs are built from it in path mode.
+    pub url: String,
+}
+
+/// The URL clients use to reach the PlanetScale HTTP API of the instance.
+pub fn instance_api_url(config: &Config, instance_name: &str) -> String {
+    match &config.proxy {
+        Some(proxy) if proxy.mode == ProxyMode::Path => {
+            format!("{}/{}", proxy.url.trim_end_matches('/'), instance_name)
+        }
+        _ => format!(
+            "https://moonscale-instance-{}.{}",
+            instance_name, config.ingress_domain
+        ),
+    }
+}
+
+/// Root passwords recently read by the proxy, so proxied requests don't each read
+/// the Secret of their instance.
+#[derive(Default)]
+pub struct PasswordCache {
+    passwords: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl PasswordCache {
+    fn get(&self, instance: &str) -> Option<String> {
+        self.passwords
+            .lock()
+            .unwrap()
+            .get(instance)
+            .filter(|(_, read_at)| read_at.elapsed() < PASSWORD_CACHE_TTL)
+            .map(|(password, _)| password.clone())
+    }
+
+    fn insert(&self, instance: &str, password: &str) {
+        let mut passwords = self.passwords.lock().unwrap();
+
+        passwords.retain(|_, (_, read_at)| read_at.elapsed() < PASSWORD_CACHE_TTL);
+        passwords.insert(instance.to_owned(), (password.to_owned(), Instant::now()));
+    }
+}
+
+/// Read the root password of the instance from its Secret, unknown instances are
+/// reported as missing.
+async fn read_root_password(context: &Context, instance: &str) -> Result<String, ErrorResponse> {
+    let api_secrets: Api<Secret> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let secret = api_secrets
+        .get_opt(&format!("moonscale-instance-{}", instance))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "Secret", started);
+    let internal_error = || {
+        error_response(
+            Status::InternalServerError,
+            "credentials_unavailable",
+            format!("Failed to check the credentials of instance {}", instance),
+        )
+    };
+    let secret = match secret {
+        Ok(Some(secret)) => secret,
+        Ok(None) => {
+            return Err(error_response(
+                Status::NotFound,
+                "instance_not_found",
+                format!("Instance {} doesn't exist", instance),
+            ))
+        }
+        Err(err) => {
+            error!("Failed to get the secret of instance {}: {}", instance, err);
+            return Err(internal_error());
+        }
+    };
+
+    secret
+        .data
+        .and_then(|mut data| data.remove("mysql-root-password"))
+        .and_then(|password| String::from_utf8(password.0).ok())
+        .ok_or_else(|| {
+            error!("The secret of instance {} has no root password", instance);
+            internal_error()
+        })
+}
+
+/// Check the Basic credentials of a proxied request against the Secret of the
+/// instance, unknown instances are reported as missing.
+pub async fn check_credentials(
+    context: &Context,
+    instance: &str,
+    authorization: Option<&str>,
+) -> Result<(), ErrorResponse> {
+    let unauthorized = || {
+        error_response(
+            Status::Unauthorized,
+            "invalid_credentials",
+            format!("Invalid credentials for instance {}", instance),
+        )
+    };
+    let (username, password) = authorization
+        .and_then(|header| header.strip_prefix("Basic "))
+        .and_then(|credentials| BASE64_STANDARD.decode(credentials.trim()).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| {
+            credentials
+                .split_once(':')
+                .map(|(username, password)| (username.to_owned(), password.to_owned()))
+        })
+        .ok_or_else(unauthorized)?;
+    let root_password = match context.proxy_passwords.get(instance) {
+        Some(root_password) => root_password,
+        None => {
+            let root_password = read_root_password(context, instance).await?;
+
+            context.proxy_passwords.insert(instance, &root_password);
+            root_password
+        }
+    };
+
+    if username != "root" || !constant_time_eq(password.as_bytes(), root_password.as_bytes()) {
+        return Err(unauthorized());
+    }
+    Ok(())
+}
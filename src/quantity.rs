@@ -0,0 +1,100 @@
+/// Suffixes of Kubernetes quantities, with the multiplier they stand for.
+const SUFFIXES: [(&str, u64); 12] = [
+    ("Ki", 1 << 10),
+    ("Mi", 1 << 20),
+    ("Gi", 1 << 30),
+    ("Ti", 1 << 40),
+    ("Pi", 1 << 50),
+    ("Ei", 1 << 60),
+    ("k", 1_000),
+    ("M", 1_000_000),
+    ("G", 1_000_000_000),
+    ("T", 1_000_000_000_000),
+    ("P", 1_000_000_000_000_000),
+    ("E", 1_000_000_000_000_000_000),
+];
+
+/// Parse a Kubernetes quantity such as `5Gi`, `500M` or `1.5Gi` into bytes,
+/// rounding fractional bytes up like Kubernetes does.
+pub fn parse_quantity(quantity: &str) -> Result<u64, String> {
+    let quantity = quantity.trim();
+    let (number, multiplier) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            quantity
+                .strip_suffix(suffix)
+                .map(|number| (number, *multiplier))
+        })
+        .unwrap_or((quantity, 1));
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid quantity {:?}", quantity))?;
+
+    if !number.is_finite() || number < 0.0 {
+        return Err(format!("Invalid quantity {:?}", quantity));
+    }
+    let bytes = (number * multiplier as f64).ceil();
+
+    // u64::MAX as f64 rounds up to 2^64, which doesn't fit
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("Quantity {:?} is too large", quantity));
+    }
+    Ok(bytes as u64)
+}
+
+/// Format bytes with the largest binary suffix that represents them exactly.
+pub fn format_quantity(bytes: u64) -> String {
+    SUFFIXES[..6]
+        .iter()
+        .rev()
+        .find(|(_, multiplier)| bytes > 0 && bytes.is_multiple_of(*multiplier))
+        .map(|(suffix, multiplier)| format!("{}{}", bytes / multiplier, suffix))
+        .unwrap_or(bytes.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_binary_suffixes() {
+        assert_eq!(parse_quantity("1Ki"), Ok(1024));
+        assert_eq!(parse_quantity("5Gi"), Ok(5 << 30));
+        assert_eq!(parse_quantity("1.5Gi"), Ok(3 << 29));
+        assert_eq!(parse_quantity(" 2Ti "), Ok(2 << 40));
+    }
+
+    #[test]
+    fn parses_decimal_suffixes() {
+        assert_eq!(parse_quantity("1k"), Ok(1_000));
+        assert_eq!(parse_quantity("500M"), Ok(500_000_000));
+        assert_eq!(parse_quantity("2G"), Ok(2_000_000_000));
+        assert_eq!(parse_quantity("1024"), Ok(1024));
+        assert_eq!(parse_quantity("0.5"), Ok(1));
+    }
+
+    #[test]
+    fn rejects_invalid_quantities() {
+        for quantity in ["", "Gi", "500m", "1K", "-1Gi", "abc", "NaN", "inf", "1 Gi"] {
+            assert!(parse_quantity(quantity).is_err(), "{:?}", quantity);
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_quantities() {
+        assert_eq!(parse_quantity("15Ei"), Ok(15 << 60));
+        assert!(parse_quantity("16Ei").is_err());
+        assert!(parse_quantity("19E").is_err());
+        assert!(parse_quantity("1e30").is_err());
+    }
+
+    #[test]
+    fn formats_with_the_largest_exact_suffix() {
+        assert_eq!(format_quantity(0), "0");
+        assert_eq!(format_quantity(1000), "1000");
+        assert_eq!(format_quantity(1024), "1Ki");
+        assert_eq!(format_quantity(5 << 30), "5Gi");
+        assert_eq!(format_quantity(3 << 29), "1536Mi");
+        assert_eq!(format_quantity(parse_quantity("10Gi").unwrap()), "10Gi");
+    }
+}
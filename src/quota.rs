@@ -0,0 +1,270 @@
+use std::{collections::HashMap, time::Instant};
+
+use k8s_openapi::api::apps::v1::StatefulSet;
+use kube::{api::ListParams, Api, Client};
+use rocket::http::Status;
+
+use crate::kubernetes::{instance_owner, instance_size};
+use crate::metrics::metrics;
+use crate::models::error::{error_response, ErrorResponse};
+use crate::quantity::{format_quantity, parse_quantity};
+
+/// Limits on what the principals can provision, unset limits aren't enforced.
+#[derive(Clone, Default)]
+pub struct QuotaLimits {
+    pub max_instances: Option<usize>,
+    pub max_instances_per_owner: Option<usize>,
+    /// Maximum storage requested by every instance together, in bytes.
+    pub max_storage: Option<u64>,
+    /// Maximum time to live of an instance, in minutes.
+    pub max_ttl: Option<usize>,
+}
+
+struct InstanceUsage {
+    owner: Option<String>,
+    storage: u64,
+}
+
+/// What the existing instances consume, keyed by instance name.
+pub struct QuotaUsage {
+    instances: HashMap<String, InstanceUsage>,
+}
+
+impl QuotaUsage {
+    pub fn instances(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn owner_instances(&self, owner: &str) -> usize {
+        self.instances
+            .values()
+            .filter(|usage| usage.owner.as_deref() == Some(owner))
+            .count()
+    }
+
+    pub fn storage(&self) -> u64 {
+        self.instances.values().map(|usage| usage.storage).sum()
+    }
+}
+
+/// Read the usage from the managed StatefulSets, terminating ones included as their
+/// volumes still hold storage.
+pub async fn quota_usage(kubeclient: &Client) -> Result<QuotaUsage, kube::Error> {
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(kubeclient.clone());
+    let started = Instant::now();
+    let managed_sts = api_sts
+        .list(&ListParams::default().labels("app.kubernetes.io/managed-by=Moonscale"))
+        .await;
+
+    metrics().observe_kubernetes_call("list", "StatefulSet", started);
+    let instances = managed_sts?
+        .items
+        .iter()
+        .filter_map(|sts| {
+            let name = sts
+                .metadata
+                .labels
+                .as_ref()?
+                .get("app.kubernetes.io/instance")?;
+            let storage = instance_size(sts)
+                .and_then(|size| parse_quantity(&size).ok())
+                .unwrap_or(0);
+
+            Some((
+                name.clone(),
+                InstanceUsage {
+                    owner: instance_owner(sts),
+                    storage,
+                },
+            ))
+        })
+        .collect();
+
+    Ok(QuotaUsage { instances })
+}
+
+impl QuotaLimits {
+    /// Check that creating `instance_name` stays within the limits. Re-applying an
+    /// existing instance doesn't count as a new one.
+    pub fn check_create(
+        &self,
+        usage: &QuotaUsage,
+        instance_name: &str,
+        owner: &str,
+        storage: u64,
+        ttl: usize,
+    ) -> Result<(), ErrorResponse> {
+        if let Some(max_ttl) = self.max_ttl.filter(|max_ttl| ttl > *max_ttl) {
+            return Err(error_response(
+                Status::Conflict,
+                "ttl_limit_exceeded",
+                format!(
+                    "The requested TTL of {}m exceeds the maximum of {}m",
+                    ttl, max_ttl
+                ),
+            ));
+        }
+        if !usage.instances.contains_key(instance_name) {
+            if let Some(max) = self.max_instances.filter(|max| usage.instances() >= *max) {
+                return Err(error_response(
+                    Status::TooManyRequests,
+                    "instance_quota_exceeded",
+                    format!("The server already runs its maximum of {} instances", max),
+                ));
+            }
+            if let Some(max) = self
+                .max_instances_per_owner
+                .filter(|max| usage.owner_instances(owner) >= *max)
+            {
+                return Err(error_response(
+                    Status::TooManyRequests,
+                    "owner_instance_quota_exceeded",
+                    format!("{} already owns its maximum of {} instances", owner, max),
+                ));
+            }
+        }
+        self.check_storage(usage, instance_name, storage)
+    }
+
+    /// Check that giving `instance_name` the requested storage stays within the
+    /// storage limit, the storage it already holds is handed back first.
+    pub fn check_storage(
+        &self,
+        usage: &QuotaUsage,
+        instance_name: &str,
+        storage: u64,
+    ) -> Result<(), ErrorResponse> {
+        let used = usage.storage()
+            - usage
+                .instances
+                .get(instance_name)
+                .map_or(0, |existing| existing.storage);
+
+        if let Some(max) = self.max_storage.filter(|max| used + storage > *max) {
+            return Err(error_response(
+                Status::Conflict,
+                "storage_quota_exceeded",
+                format!(
+                    "The requested storage of {} exceeds the {} left out of {}",
+                    format_quantity(storage),
+                    format_quantity(max.saturating_sub(used)),
+                    format_quantity(max)
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(instances: &[(&str, &str, u64)]) -> QuotaUsage {
+        QuotaUsage {
+            instances: instances
+                .iter()
+                .map(|(name, owner, storage)| {
+                    (
+                        name.to_string(),
+                        InstanceUsage {
+                            owner: Some(owner.to_string()),
+                            storage: *storage,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn error_code(result: Result<(), ErrorResponse>) -> Option<String> {
+        result.err().map(|(_, body)| body.error.clone())
+    }
+
+    #[test]
+    fn unset_limits_are_not_enforced() {
+        let usage = usage(&[("a", "alice", 1 << 40), ("b", "alice", 1 << 40)]);
+
+        assert!(QuotaLimits::default()
+            .check_create(&usage, "c", "alice", 1 << 40, 100_000)
+            .is_ok());
+    }
+
+    #[test]
+    fn limits_the_number_of_instances() {
+        let usage = usage(&[("a", "alice", 0), ("b", "bob", 0)]);
+        let limits = QuotaLimits {
+            max_instances: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            error_code(limits.check_create(&usage, "c", "carol", 0, 60)).as_deref(),
+            Some("instance_quota_exceeded")
+        );
+        // Re-applying an existing instance doesn't take a new slot
+        assert!(limits.check_create(&usage, "a", "alice", 0, 60).is_ok());
+    }
+
+    #[test]
+    fn limits_the_instances_of_each_owner() {
+        let usage = usage(&[("a", "alice", 0), ("b", "bob", 0)]);
+        let limits = QuotaLimits {
+            max_instances_per_owner: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            error_code(limits.check_create(&usage, "c", "alice", 0, 60)).as_deref(),
+            Some("owner_instance_quota_exceeded")
+        );
+        assert!(limits.check_create(&usage, "c", "carol", 0, 60).is_ok());
+    }
+
+    #[test]
+    fn limits_the_ttl() {
+        let limits = QuotaLimits {
+            max_ttl: Some(60),
+            ..Default::default()
+        };
+
+        assert!(limits
+            .check_create(&usage(&[]), "a", "alice", 0, 60)
+            .is_ok());
+        assert_eq!(
+            error_code(limits.check_create(&usage(&[]), "a", "alice", 0, 61)).as_deref(),
+            Some("ttl_limit_exceeded")
+        );
+    }
+
+    #[test]
+    fn limits_the_storage() {
+        let usage = usage(&[("a", "alice", 6 << 30), ("b", "bob", 2 << 30)]);
+        let limits = QuotaLimits {
+            max_storage: Some(10 << 30),
+            ..Default::default()
+        };
+
+        assert!(limits.check_storage(&usage, "c", 2 << 30).is_ok());
+        assert_eq!(
+            error_code(limits.check_storage(&usage, "c", 3 << 30)).as_deref(),
+            Some("storage_quota_exceeded")
+        );
+        assert_eq!(
+            error_code(limits.check_create(&usage, "c", "carol", 3 << 30, 60)).as_deref(),
+            Some("storage_quota_exceeded")
+        );
+    }
+
+    #[test]
+    fn resizing_hands_back_the_current_storage() {
+        let usage = usage(&[("a", "alice", 6 << 30), ("b", "bob", 2 << 30)]);
+        let limits = QuotaLimits {
+            max_storage: Some(10 << 30),
+            ..Default::default()
+        };
+
+        assert!(limits.check_storage(&usage, "a", 8 << 30).is_ok());
+        assert!(limits.check_storage(&usage, "a", 9 << 30).is_err());
+    }
+}
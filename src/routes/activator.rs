@@ -0,0 +1,194 @@
+use std::io::Cursor;
+
+use crate::activator::{wake_instance, ProxiedResponse};
+use crate::context::Context;
+use crate::models::error::{error_response, ErrorResponse};
+use crate::proxy::{check_credentials, ProxyMode};
+use log::debug;
+use rocket::data::{ByteUnit, Data};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome};
+use rocket::response::{self, Responder};
+use rocket::{delete, get, patch, post, put, Request, Response, State};
+
+/// Largest request body held while an instance wakes up.
+const ACTIVATOR_BODY_LIMIT: ByteUnit = ByteUnit::Mebibyte(8);
+
+/// A request sent to the host of an instance, `moonscale-instance-<name>.<domain>`,
+/// which reaches moonscale while the instance is paused, or always with the built-in
+/// proxy in host mode. Requests to any other host are forwarded to the regular routes.
+pub struct ActivatorRequest {
+    instance: String,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+/// Read the body of a proxied request, which is held in memory while the instance
+/// wakes up.
+pub async fn read_proxied_body(body: Option<Data<'_>>) -> Result<Vec<u8>, ErrorResponse> {
+    let Some(body) = body else {
+        return Ok(vec![]);
+    };
+    let body = body
+        .open(ACTIVATOR_BODY_LIMIT)
+        .into_bytes()
+        .await
+        .map_err(|err| {
+            error_response(
+                Status::BadRequest,
+                "invalid_body",
+                format!("Failed to read the request body: {}", err),
+            )
+        })?;
+
+    if !body.is_complete() {
+        return Err(error_response(
+            Status::PayloadTooLarge,
+            "body_too_large",
+            format!("Request bodies are limited to {}", ACTIVATOR_BODY_LIMIT),
+        ));
+    }
+    Ok(body.into_inner())
+}
+
+/// Check the credentials when moonscale is the proxy of the instances, wake the
+/// instance up if needed, then forward the request to it.
+pub async fn proxy_to_instance(
+    context: &Context,
+    instance: &str,
+    method: &str,
+    path: &str,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+) -> Result<ProxiedResponse, ErrorResponse> {
+    if context.config.proxy.is_some() {
+        let authorization = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+            .map(|(_, value)| value.as_str());
+
+        check_credentials(context, instance, authorization).await?;
+    }
+    wake_instance(context, instance).await?;
+    context
+        .activator
+        .forward(context, instance, method, path, headers, body)
+        .await
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ActivatorRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let context = request.rocket().state::<Context>().unwrap();
+        let instance = request.host().and_then(|host| {
+            host.domain()
+                .as_str()
+                .strip_suffix(&format!(".{}", context.config.ingress_domain))?
+                .strip_prefix("moonscale-instance-")
+                .map(str::to_owned)
+        });
+
+        let host_proxy = context
+            .config
+            .proxy
+            .as_ref()
+            .is_some_and(|proxy| proxy.mode == ProxyMode::Host);
+
+        match instance {
+            Some(instance) if context.config.activator.is_some() || host_proxy => {
+                Outcome::Success(ActivatorRequest {
+                    instance,
+                    method: request.method().as_str().to_owned(),
+                    path: request.uri().to_string(),
+                    headers: request
+                        .headers()
+                        .iter()
+                        .map(|header| (header.name().to_string(), header.value().to_owned()))
+                        .collect(),
+                })
+            }
+            _ => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ProxiedResponse {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+
+        response.status(Status::new(self.status));
+        for (name, value) in self.headers {
+            response.raw_header_adjoin(name, value);
+        }
+        response.sized_body(self.body.len(), Cursor::new(self.body));
+        response.ok()
+    }
+}
+
+async fn activate(
+    request: ActivatorRequest,
+    body: Option<Data<'_>>,
+    context: &Context,
+) -> Result<ProxiedResponse, ErrorResponse> {
+    let body = read_proxied_body(body).await?;
+
+    debug!(
+        instance = request.instance.as_str();
+        "Received {} {} for instance {}", request.method, request.path, request.instance
+    );
+    proxy_to_instance(
+        context,
+        &request.instance,
+        &request.method,
+        &request.path,
+        request.headers,
+        body,
+    )
+    .await
+}
+
+#[get("/<_..>")]
+pub async fn route_activator_get(
+    request: ActivatorRequest,
+    context: &State<Context>,
+) -> Result<ProxiedResponse, ErrorResponse> {
+    activate(request, None, context).await
+}
+
+#[post("/<_..>", data = "<body>")]
+pub async fn route_activator_post(
+    request: ActivatorRequest,
+    body: Data<'_>,
+    context: &State<Context>,
+) -> Result<ProxiedResponse, ErrorResponse> {
+    activate(request, Some(body), context).await
+}
+
+#[put("/<_..>", data = "<body>")]
+pub async fn route_activator_put(
+    request: ActivatorRequest,
+    body: Data<'_>,
+    context: &State<Context>,
+) -> Result<ProxiedResponse, ErrorResponse> {
+    activate(request, Some(body), context).await
+}
+
+#[patch("/<_..>", data = "<body>")]
+pub async fn route_activator_patch(
+    request: ActivatorRequest,
+    body: Data<'_>,
+    context: &State<Context>,
+) -> Result<ProxiedResponse, ErrorResponse> {
+    activate(request, Some(body), context).await
+}
+
+#[delete("/<_..>")]
+pub async fn route_activator_delete(
+    request: ActivatorRequest,
+    context: &State<Context>,
+) -> Result<ProxiedResponse, ErrorResponse> {
+    activate(request, None, context).await
+}
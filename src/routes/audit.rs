@@ -0,0 +1,50 @@
+use crate::{
+    audit::AuditFilter, middlewares::authentication::ApiKey, models::audit::ListAuditResponseModel,
+};
+use rocket::{get, http::Status, response::status, serde::json::Json, State};
+use rocket_okapi::openapi;
+
+/// # Query the audit log
+///
+/// This route returns the most recent mutating API calls, newest first. Every
+/// filter is optional, `since` is an RFC 3339 timestamp. Only admin keys see the
+/// calls of other principals.
+#[openapi(tag = "Audit")]
+#[get("/audit?<principal>&<action>&<instance>&<outcome>&<since>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn route_list_audit(
+    principal: Option<&str>,
+    action: Option<&str>,
+    instance: Option<&str>,
+    outcome: Option<&str>,
+    since: Option<&str>,
+    limit: Option<usize>,
+    context: &State<crate::context::Context>,
+    key: ApiKey,
+) -> Result<status::Custom<Json<ListAuditResponseModel>>, Status> {
+    let since = match since.map(humantime::parse_rfc3339_weak) {
+        Some(Err(_)) => return Err(Status::BadRequest),
+        Some(Ok(since)) => Some(since),
+        None => None,
+    };
+    let principal = match principal {
+        _ if key.admin => principal,
+        Some(principal) if principal != key.principal => {
+            return Ok(status::Custom(Status::Ok, Json(vec![])))
+        }
+        _ => Some(key.principal.as_str()),
+    };
+    let filter = AuditFilter {
+        principal,
+        action,
+        instance,
+        outcome,
+        since,
+        limit,
+    };
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(context.audit.query(&filter)),
+    ))
+}
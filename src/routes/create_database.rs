@@ -1,120 +1,373 @@
-use crate::middlewares::authentication::ApiKey;
-use crate::models::database::CreateDatabaseResponseModel;
-use crate::{kubernetes::kubernetes_apply_document, models::database::CreateDatabaseRequestModel};
-use anyhow::{Context, Result};
-use base64::prelude::*;
-use kube::{api::PatchParams, Discovery};
-use log::error;
+use std::time::Instant;
+
+use crate::audit::audit_record;
+use crate::kubernetes::{instance_owner, wait_for_instance_ready};
+use crate::metadata::InstanceMetadata;
+use crate::metrics::metrics;
+use crate::middlewares::{authentication::ApiKey, tracing::RequestTrace};
+use crate::models::audit::AuditAction;
+use crate::models::database::{CreateDatabaseResponseModel, DatabaseInstanceModel, ResourcePreset};
+use crate::models::error::{error_response, ErrorResponse};
+use crate::models::operation::{
+    OperationModel, OperationType, ResourceProgressModel, ResourceState,
+};
+use crate::profiles::{resolve_profile, Profile};
+use crate::proxy::instance_api_url;
+use crate::quantity::format_quantity;
+use crate::quota::quota_usage;
+use crate::telemetry::in_span_sync;
+use crate::template::{
+    ensure_instance_scoped, instance_auto_pause, instance_template_context, instance_ttl,
+    multidoc_deserialize, validate_instance_name,
+};
+use crate::{
+    kubernetes::{kubernetes_apply_document, publish_lifecycle_event, InstanceEvent},
+    models::database::CreateDatabaseRequestModel,
+};
+use anyhow::Result;
+use k8s_openapi::api::apps::v1::StatefulSet;
+use kube::{api::PatchParams, Api};
+use log::{error, warn};
+use opentelemetry::{trace::FutureExt, KeyValue};
 use rand::distributions::Alphanumeric;
 use rand::distributions::DistString;
 use rocket::response::status::{self};
 use rocket::{http::Status, post, serde::json::Json, State};
 use rocket_okapi::openapi;
-use serde::Deserialize;
-use tera::Tera;
-
-fn multidoc_deserialize(
-    data: &str,
-    context: &mut tera::Context,
-) -> Result<Vec<serde_yaml::Value>, anyhow::Error> {
-    let mut docs = vec![];
-    let mut tera = Tera::default();
-
-    tera.add_raw_template("template", data)
-        .context("Rendering error")?;
-    let render_result = tera
-        .render("template", context)
-        .context("Failed to render template, check your yaml file.")?;
-
-    for de in serde_yaml::Deserializer::from_str(render_result.as_str()) {
-        let dedoc = serde_yaml::Value::deserialize(de)
-            .context("Couldn't deserialize yaml. Check format.")?;
-
-        docs.push(dedoc);
-    }
-    Ok(docs)
+
+/// The validated settings of the instance to create.
+struct InstanceSpec<'a> {
+    metadata: InstanceMetadata,
+    profile: &'a Profile,
+    preset: ResourcePreset,
+    /// The storage of the instance, in bytes.
+    size: u64,
 }
 
 async fn create_database(
     template_data: &str,
     variable_data: &CreateDatabaseRequestModel,
+    spec: &InstanceSpec<'_>,
+    operation: &OperationModel,
     context: &crate::context::Context,
-    discovery: &Discovery,
-) -> Result<CreateDatabaseResponseModel, anyhow::Error> {
+) -> Result<DatabaseInstanceModel, anyhow::Error> {
     let ssapply = PatchParams::apply("kubectl-light").force();
-    let mut template_context: tera::Context = tera::Context::new();
     let random_password = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
-
-    template_context.insert("name", variable_data.name.as_str());
-    template_context.insert("domain", context.config.ingress_domain.as_str());
-    template_context.insert("resource_ttl", &context.config.resource_ttl);
-    // TODO: This will be a problem if you create a database that already exists, as
-    // the password will be different from what mysql expects
-    // and also because the pod isn't restarted when the secret is updated
-    template_context.insert(
-        "root_password",
-        &BASE64_STANDARD.encode(random_password.as_str()),
+    let mut template_context = instance_template_context(
+        &context.config,
+        &variable_data.name,
+        &operation.principal,
+        &random_password,
+        spec.profile,
+        &format_quantity(spec.size),
+        spec.preset,
     );
-    // TODO: Check size formatting
-    template_context.insert(
-        "pvc_size",
-        format!("{}Gi", num::clamp(variable_data.size, 1, 5)).as_str(),
-    );
-    for doc in multidoc_deserialize(&template_data, &mut template_context)? {
-        let _ =
-            kubernetes_apply_document(&context.kubernetes_client, discovery, &ssapply, doc).await;
-        // let obj: DynamicObject = serde_yaml::from_value(doc)?;
+
+    let docs = in_span_sync(
+        "template.render",
+        vec![KeyValue::new(
+            "moonscale.instance",
+            variable_data.name.clone(),
+        )],
+        || multidoc_deserialize(template_data, &mut template_context),
+    )?;
+
+    // Refuse the whole template rather than applying part of it
+    for doc in &docs {
+        ensure_instance_scoped(&variable_data.name, doc)?;
     }
+    let mut failures = vec![];
+
+    for doc in docs {
+        let kind = doc["kind"].as_str().unwrap_or("Resource").to_owned();
+        let name = doc["metadata"]["name"].as_str().unwrap_or("").to_owned();
 
-    Ok(CreateDatabaseResponseModel {
-        database_name: variable_data.name.clone(),
-        database_password: random_password,
-        database_username: "root".to_owned(),
-        planetscale_api_url: format!(
-            "https://moonscale-instance-{}.{}",
-            variable_data.name, context.config.ingress_domain
+        if let Err(err) = kubernetes_apply_document(
+            &context.kubernetes_client,
+            &variable_data.name,
+            &context.discovery,
+            &ssapply,
+            &spec.metadata,
+            doc,
         )
-        .to_string(),
-    })
-}
+        .await
+        {
+            failures.push(format!("{}/{}", kind, name));
+            context.operations.update(&operation.id, |operation| {
+                operation.resources.push(ResourceProgressModel {
+                    kind,
+                    name,
+                    state: ResourceState::Failed,
+                    attempts: 1,
+                    error: Some(err.to_string()),
+                })
+            });
+        }
+    }
+    if !failures.is_empty() {
+        let message = format!(
+            "{} resources were rejected: {}",
+            failures.len(),
+            failures.join(", ")
+        );
 
-/// # Create a PlanetScale's compatible database
-///
-/// This route is used to create a PlanetScale's compatible database.
-#[openapi(tag = "Database")]
-#[post("/database", data = "<request>")]
-pub async fn route_create_database(
-    context: &State<crate::context::Context>,
-    request: Json<CreateDatabaseRequestModel>,
-    _key: ApiKey,
-) -> Result<status::Custom<Json<CreateDatabaseResponseModel>>, Status> {
-    let discovery = Discovery::new(context.kubernetes_client.clone())
-        .run()
+        publish_lifecycle_event(
+            &context.kubernetes_client,
+            &variable_data.name,
+            InstanceEvent::CreateFailed,
+            format!(
+                "Failed to create moonscale instance {}, {}",
+                variable_data.name, message
+            ),
+        )
         .await;
-
-    if discovery.is_err() {
-        error!("Failed to discover Kubernetes API");
-        return Err(Status::InternalServerError);
+        return Err(anyhow::anyhow!(message));
     }
+    publish_lifecycle_event(
+        &context.kubernetes_client,
+        &variable_data.name,
+        InstanceEvent::Created,
+        format!("Created moonscale instance {}", variable_data.name),
+    )
+    .await;
 
+    let mut instance = DatabaseInstanceModel::new(
+        &variable_data.name,
+        random_password,
+        instance_api_url(&context.config, &variable_data.name),
+    );
+
+    let user_metadata = spec.metadata.user_metadata();
+
+    instance.labels = user_metadata.labels;
+    instance.annotations = user_metadata.annotations;
+    Ok(instance)
+}
+
+async fn create_database_response(
+    context: &crate::context::Context,
+    request: &CreateDatabaseRequestModel,
+    spec: &InstanceSpec<'_>,
+    operation: &OperationModel,
+) -> Result<status::Custom<Json<CreateDatabaseResponseModel>>, ErrorResponse> {
     let database_creation_result = create_database(
         &context.database_template_yaml_raw,
-        &request.0,
-        &context,
-        &discovery.unwrap(),
+        request,
+        spec,
+        operation,
+        context,
     )
     .await;
 
+    // A create only succeeds once every document of the template was applied
+    metrics()
+        .database_creates
+        .with_label_values(&[if database_creation_result.is_ok() {
+            "success"
+        } else {
+            "failure"
+        }])
+        .inc();
     if database_creation_result.is_err() {
+        let err = database_creation_result.err().unwrap();
+
         error!(
+            instance = request.name.as_str();
             "Failed to create database: {:?}",
-            database_creation_result.err()
+            err
         );
-        return Err(Status::InternalServerError);
+        context
+            .operations
+            .finish(&operation.id, Some(err.to_string()));
+        return Err(error_response(
+            Status::InternalServerError,
+            "creation_failed",
+            "Failed to create the database",
+        ));
     }
 
     Ok(status::Custom(
         Status::Created,
-        Json(database_creation_result.unwrap()),
+        Json(CreateDatabaseResponseModel {
+            instance: database_creation_result.unwrap(),
+            operation_id: operation.id.clone(),
+        }),
     ))
 }
+
+/// Wait for the instance to be ready in the background and complete its operation.
+async fn track_instance_readiness(
+    context: crate::context::Context,
+    instance_name: String,
+    operation_id: String,
+    started: Instant,
+) {
+    let error = match wait_for_instance_ready(&context.kubernetes_client, &instance_name).await {
+        Ok(true) => {
+            metrics()
+                .time_to_ready
+                .observe(started.elapsed().as_secs_f64());
+            None
+        }
+        Ok(false) => {
+            warn!(
+                "Instance {} didn't become ready before the timeout",
+                instance_name
+            );
+            Some("The instance didn't become ready before the timeout".to_owned())
+        }
+        Err(err) => {
+            warn!(
+                "Failed to wait for instance {} readiness: {}",
+                instance_name, err
+            );
+            Some(format!(
+                "Failed to wait for the instance readiness: {}",
+                err
+            ))
+        }
+    };
+
+    context.operations.finish(&operation_id, error);
+}
+
+/// Create the instance and track its readiness in the background, shared by the
+/// REST API and the pull request integrations.
+pub async fn start_create_database(
+    context: &crate::context::Context,
+    request: &CreateDatabaseRequestModel,
+    key: &ApiKey,
+) -> Result<status::Custom<Json<CreateDatabaseResponseModel>>, ErrorResponse> {
+    let started = Instant::now();
+    let principal = key.principal.as_str();
+    let ttl = instance_ttl(&context.config, request.ttl);
+    let reject = |error: &str, message: String| {
+        warn!(
+            instance = request.name.as_str();
+            "Rejected the creation of instance {}: {}", request.name, message
+        );
+        error_response(Status::BadRequest, error, message)
+    };
+    validate_instance_name(
+        &context.config,
+        &context.database_template_yaml_raw,
+        &request.name,
+    )
+    .map_err(|err| reject("invalid_name", err))?;
+    let profile = resolve_profile(&context.config, request.profile.as_deref())
+        .map_err(|err| reject("invalid_profile", err))?;
+    let size = profile
+        .resolve_size(request.size.as_deref())
+        .map_err(|err| reject("invalid_size", err))?;
+    let preset = request.preset.unwrap_or(profile.default_preset);
+    let metadata = InstanceMetadata::from_user(&request.labels, &request.annotations)
+        .map_err(|err| reject("invalid_metadata", err))?
+        .with_system_metadata(&request.name, principal, &profile.name, preset, ttl)
+        .with_auto_pause(instance_auto_pause(&context.config, request.auto_pause));
+    let spec = InstanceSpec {
+        metadata,
+        profile,
+        preset,
+        size,
+    };
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let get_started = Instant::now();
+    let existing = api_sts
+        .get_opt(&format!("moonscale-instance-{}", request.name))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "StatefulSet", get_started);
+    match existing {
+        Ok(None) => {}
+        // Applying over the instance would rotate its password, which MySQL ignores
+        Ok(Some(sts)) if key.can_access(instance_owner(&sts).as_deref()) => {
+            return Err(error_response(
+                Status::Conflict,
+                "instance_exists",
+                format!("Instance {} already exists", request.name),
+            ))
+        }
+        // Instances of other principals are reported as missing rather than forbidden
+        Ok(Some(_)) => {
+            return Err(error_response(
+                Status::NotFound,
+                "instance_not_found",
+                format!("Instance {} can't be created", request.name),
+            ))
+        }
+        Err(err) => {
+            error!("Failed to get instance {}: {}", request.name, err);
+            return Err(error_response(
+                Status::InternalServerError,
+                "creation_failed",
+                "Failed to check if the instance exists",
+            ));
+        }
+    }
+    let _quota_guard = context.quota_lock.lock().await;
+    let usage = quota_usage(&context.kubernetes_client)
+        .await
+        .map_err(|err| {
+            error!("Failed to read the quota usage: {}", err);
+            error_response(
+                Status::InternalServerError,
+                "quota_unavailable",
+                "Failed to read the quota usage",
+            )
+        })?;
+
+    context
+        .config
+        .quota
+        .check_create(&usage, &request.name, principal, size, ttl)?;
+    let operation = context
+        .operations
+        .create(OperationType::Create, &request.name, principal);
+    let response = create_database_response(context, request, &spec, &operation).await;
+
+    if response.is_ok() {
+        // Track the readiness in the background, the caller doesn't wait for it
+        rocket::tokio::spawn(
+            track_instance_readiness(context.clone(), request.name.clone(), operation.id, started)
+                .with_current_context(),
+        );
+    }
+    response
+}
+
+/// # Create a PlanetScale's compatible database
+///
+/// This route is used to create a PlanetScale's compatible database.
+#[openapi(tag = "Database")]
+#[post("/database", data = "<request>")]
+pub async fn route_create_database(
+    context: &State<crate::context::Context>,
+    request: Json<CreateDatabaseRequestModel>,
+    key: ApiKey,
+    trace: RequestTrace,
+) -> Result<status::Custom<Json<CreateDatabaseResponseModel>>, ErrorResponse> {
+    let started = Instant::now();
+    let response = start_create_database(context, &request.0, &key)
+        .with_context(trace.context())
+        .await;
+    let status = match &response {
+        Ok(created) => created.0,
+        Err((status, _)) => *status,
+    };
+
+    context
+        .audit
+        .record(
+            &context.kubernetes_client,
+            audit_record(
+                &key.principal,
+                AuditAction::Create,
+                "POST /api/database",
+                &request.name,
+                status,
+                started,
+            ),
+        )
+        .await;
+    response
+}
@@ -1,117 +1,369 @@
-use crate::middlewares::authentication::ApiKey;
-use kube::{
-    api::{DeleteParams, DynamicObject, ListParams},
-    discovery::{verbs, Scope},
-    Api, Discovery, ResourceExt,
+use std::time::{Duration, Instant};
+
+use crate::audit::audit_record;
+use crate::context::Context;
+use crate::kubernetes::{
+    instance_owner, kubernetes_delete_resource, kubernetes_get_resource, publish_lifecycle_event,
+    DeleteOutcome, InstanceEvent,
+};
+use crate::metrics::metrics;
+use crate::middlewares::{authentication::ApiKey, tracing::RequestTrace};
+use crate::models::audit::AuditAction;
+use crate::models::operation::{
+    OperationModel, OperationType, ResourceProgressModel, ResourceState,
 };
-use log::{info, warn};
+use crate::template::{rendered_instance_resources, InstanceResource};
+use k8s_openapi::api::{apps::v1::StatefulSet, core::v1::Secret};
+use kube::{api::DynamicObject, Api, ResourceExt};
+use log::{error, info, warn};
+use opentelemetry::trace::FutureExt;
+use rocket::futures::future::join_all;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::tokio::time::sleep;
 use rocket::{delete, http::Status, State};
 use rocket_okapi::openapi;
 
-async fn delete_database(
-    instance: &str,
-    context: &crate::context::Context,
-) -> Result<(), anyhow::Error> {
-    let discovery = Discovery::new(context.kubernetes_client.clone())
-        .run()
-        .await?;
-    let mut deleted: bool = false;
-
-    for group in discovery.groups() {
-        for (ar, caps) in group.recommended_resources() {
-            if !caps.supports_operation(verbs::DELETE) || !caps.supports_operation(verbs::LIST) {
-                continue;
-            }
-            let api: Api<DynamicObject> = if caps.scope == Scope::Cluster {
-                Api::all_with(context.kubernetes_client.clone(), &ar)
-            } else {
-                Api::namespaced_with(context.kubernetes_client.clone(), "moonscale", &ar)
-            };
-
-            let list = api
-                .list(
-                    &ListParams::default().labels(
-                        format!(
-                            "app.kubernetes.io/managed-by=Moonscale,app.kubernetes.io/instance={}",
-                            instance
-                        )
-                        .as_str(),
-                    ), //.labels(format!("app.kubernetes.io/managed-by=Moonscale").as_str()
-                )
-                .await;
-
-            if list.is_err() {
-                warn!(
-                    "Failed to list resources while deleting instance {} ({}/{})",
-                    instance,
-                    ar.kind,
-                    list.err().unwrap()
-                );
-                continue;
-            }
+/// How many delete orders we send for a resource before giving up on it.
+const DELETE_MAX_ATTEMPTS: u32 = 5;
+/// How long we wait for a resource to disappear after each delete order.
+const DELETE_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const DELETE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Base of the exponential backoff between two delete orders.
+const DELETE_BACKOFF_BASE: Duration = Duration::from_secs(2);
+
+/// Whether the object belongs to the instance: resources are looked up by name, and
+/// a name rendered for this instance may be taken by another one (eg: the `-ps`
+/// Service of `foo` for the instance `foo-ps`).
+fn is_instance_object(obj: &DynamicObject, instance: &str) -> bool {
+    let labels = obj.labels();
+
+    labels.get("app.kubernetes.io/instance").map(String::as_str) == Some(instance)
+        && labels
+            .get("app.kubernetes.io/managed-by")
+            .map(String::as_str)
+            == Some("Moonscale")
+}
+
+/// Poll the resource until it disappears, returns it if it's still present at the deadline.
+async fn wait_for_resource_removal(
+    context: &Context,
+    resource: &InstanceResource,
+) -> Result<Option<DynamicObject>, anyhow::Error> {
+    let deadline = Instant::now() + DELETE_WAIT_TIMEOUT;
+
+    loop {
+        let current =
+            kubernetes_get_resource(&context.kubernetes_client, &context.discovery, resource)
+                .await?;
+
+        if current.is_none() || Instant::now() >= deadline {
+            return Ok(current);
+        }
+        sleep(DELETE_POLL_INTERVAL).await;
+    }
+}
+
+/// Delete a resource and wait for it to be gone, sending new delete orders with an
+/// exponential backoff while it's still present (eg: stuck on a finalizer).
+async fn delete_resource_with_retries(
+    context: &Context,
+    operation_id: &str,
+    index: usize,
+    resource: &InstanceResource,
+) -> bool {
+    let set_progress = |state: ResourceState, attempts: u32, error: Option<String>| {
+        context.operations.update(operation_id, |operation| {
+            let progress = &mut operation.resources[index];
 
-            for item in list.unwrap().items {
-                let name = item.name_any();
-                let ns = item.metadata.namespace.map(|s| s + "/").unwrap_or_default();
+            progress.state = state;
+            progress.attempts = attempts;
+            progress.error = error;
+        })
+    };
 
+    for attempt in 1..=DELETE_MAX_ATTEMPTS {
+        set_progress(ResourceState::Deleting, attempt, None);
+        let error = match kubernetes_delete_resource(
+            &context.kubernetes_client,
+            &context.discovery,
+            resource,
+        )
+        .await
+        {
+            DeleteOutcome::NotFound => None,
+            DeleteOutcome::Failed(err) => Some(err),
+            DeleteOutcome::Deleted => match wait_for_resource_removal(context, resource).await {
+                Ok(None) => None,
+                Ok(Some(current)) => Some(format!(
+                    "Still present {}s after the delete order, finalizers: {:?}",
+                    DELETE_WAIT_TIMEOUT.as_secs(),
+                    current.metadata.finalizers.unwrap_or_default()
+                )),
+                Err(err) => Some(err.to_string()),
+            },
+        };
+
+        match error {
+            None => {
                 info!(
-                    "Deleting resource: namespace: {}, resource: {}, name: {}",
-                    ns, ar.kind, name
+                    "Deleted resource: resource: {}, name: {}",
+                    resource.gvk.kind, resource.name
                 );
-                let delete_result = api.delete(name.as_str(), &DeleteParams::foreground()).await;
-
-                if delete_result.is_err() {
-                    warn!(
-                        "Failed to cleanup resource while deleting instance {} ({}/{})",
-                        instance, ar.kind, name
-                    );
+                set_progress(ResourceState::Deleted, attempt, None);
+                return true;
+            }
+            Some(err) => {
+                warn!(
+                    "Failed to cleanup resource while deleting instance ({}/{}), attempt {}/{}: {}",
+                    resource.gvk.kind, resource.name, attempt, DELETE_MAX_ATTEMPTS, err
+                );
+                set_progress(ResourceState::Deleting, attempt, Some(err.clone()));
+                if attempt == DELETE_MAX_ATTEMPTS {
+                    set_progress(ResourceState::Failed, attempt, Some(err));
                 } else {
-                    deleted = true;
+                    sleep(DELETE_BACKOFF_BASE * 2u32.pow(attempt - 1)).await;
                 }
             }
         }
     }
+    false
+}
+
+/// Delete every resource of the instance in parallel, tracking progress on the operation.
+async fn delete_database(
+    instance: String,
+    resources: Vec<InstanceResource>,
+    context: Context,
+    operation_id: String,
+) {
+    let results = join_all(resources.iter().enumerate().map(|(index, resource)| {
+        delete_resource_with_retries(&context, &operation_id, index, resource)
+    }))
+    .await;
+    let failures = results.iter().filter(|deleted| !**deleted).count();
+
+    if failures == 0 {
+        publish_lifecycle_event(
+            &context.kubernetes_client,
+            &instance,
+            InstanceEvent::Deleted,
+            format!("Deleted moonscale instance {}", instance),
+        )
+        .await;
+        metrics()
+            .database_deletes
+            .with_label_values(&["success"])
+            .inc();
+        context.operations.finish(&operation_id, None);
+    } else {
+        error!(
+            instance = instance.as_str();
+            "{} resources of instance {} couldn't be deleted",
+            failures, instance
+        );
+        metrics()
+            .database_deletes
+            .with_label_values(&["failure"])
+            .inc();
+        context.operations.finish(
+            &operation_id,
+            Some(format!("{} resources couldn't be deleted", failures)),
+        );
+    }
+}
+
+/// Start deleting the instance in the background, returns the operation tracking it.
+pub async fn start_delete_database(
+    instance: &str,
+    principal: &str,
+    context: &Context,
+) -> Result<OperationModel, Status> {
+    info!(instance = instance; "Deleting moonscale instance {}", instance);
+    let resources = rendered_instance_resources(
+        &context.config,
+        &context.database_template_yaml_raw,
+        instance,
+    )
+    .map_err(|err| {
+        error!(
+            "Failed to render resources of instance {}: {:?}",
+            instance, err
+        );
+        Status::InternalServerError
+    })?;
+    let existing = join_all(resources.iter().map(|resource| {
+        kubernetes_get_resource(&context.kubernetes_client, &context.discovery, resource)
+    }))
+    .await;
+
+    if existing.iter().any(|current| current.is_err()) {
+        error!("Failed to check the resources of instance {}", instance);
+        return Err(Status::InternalServerError);
+    }
+    let resources: Vec<InstanceResource> = resources
+        .into_iter()
+        .zip(existing)
+        .filter_map(|(resource, current)| match current {
+            Ok(Some(current)) if is_instance_object(&current, instance) => Some(resource),
+            Ok(Some(_)) => {
+                warn!(
+                    "Skipping {}/{}, it doesn't belong to instance {}",
+                    resource.gvk.kind, resource.name, instance
+                );
+                None
+            }
+            _ => None,
+        })
+        .collect();
+
+    if resources.is_empty() {
+        warn!(
+            "No resources found for instance {}, skipping delete order.",
+            instance
+        );
+        return Err(Status::NotFound);
+    }
+
+    let operation = context
+        .operations
+        .create(OperationType::Delete, instance, principal);
+
+    context.operations.update(&operation.id, |operation| {
+        operation.resources = resources
+            .iter()
+            .map(|resource| ResourceProgressModel {
+                kind: resource.gvk.kind.clone(),
+                name: resource.name.clone(),
+                state: ResourceState::Deleting,
+                attempts: 0,
+                error: None,
+            })
+            .collect();
+    });
+    rocket::tokio::spawn(
+        delete_database(
+            instance.to_owned(),
+            resources,
+            context.clone(),
+            operation.id.clone(),
+        )
+        .with_current_context(),
+    );
+    Ok(context.operations.get(&operation.id).unwrap_or(operation))
+}
 
-    match deleted {
-        true => Ok(()),
-        false => {
-            warn!(
-                "No resources found for instance {}, or couldn't delete them skipping delete order.",
-                instance
-            );
-            Err(anyhow::anyhow!(
-                "No resources found for instance {}",
-                instance
-            ))
+/// The owner of an instance without StatefulSet, from the labels of its Secret.
+async fn leftover_instance_owner(
+    instance: &str,
+    context: &Context,
+) -> Result<Option<String>, Status> {
+    let api_secrets: Api<Secret> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let secret = api_secrets
+        .get_metadata_opt(&format!("moonscale-instance-{}", instance))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "Secret", started);
+    let secret = secret.map_err(|err| {
+        error!("Failed to get the secret of instance {}: {}", instance, err);
+        Status::InternalServerError
+    })?;
+
+    Ok(secret.and_then(|secret| {
+        let labels = secret.labels();
+
+        if labels.get("app.kubernetes.io/instance").map(String::as_str) != Some(instance)
+            || labels
+                .get("app.kubernetes.io/managed-by")
+                .map(String::as_str)
+                != Some("Moonscale")
+        {
+            return None;
         }
+        labels
+            .get("moonscale/owner")
+            .filter(|owner| !owner.is_empty())
+            .cloned()
+    }))
+}
+
+/// Delete the instance when the key can access it, instances of other principals
+/// are reported as missing rather than forbidden.
+async fn delete_accessible_database(
+    instance: &str,
+    key: &ApiKey,
+    context: &Context,
+) -> Result<OperationModel, Status> {
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let sts = api_sts
+        .get_opt(&format!("moonscale-instance-{}", instance))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "StatefulSet", started);
+    let sts = sts.map_err(|err| {
+        error!("Failed to get instance {}: {}", instance, err);
+        Status::InternalServerError
+    })?;
+    let owner = match sts {
+        Some(sts) => instance_owner(&sts),
+        // The StatefulSet may have failed to apply, read the owner of the Secret
+        None => leftover_instance_owner(instance, context).await?,
+    };
+
+    if !key.can_access(owner.as_deref()) {
+        return Err(Status::NotFound);
     }
+    start_delete_database(instance, &key.principal, context).await
 }
 
 /// # Delete a managed database
 ///
-/// This route is used to delete a deployed moonscale database.
+/// This route is used to delete a deployed moonscale database. The deletion runs
+/// in the background, poll `GET /api/operations/<id>` to follow its progress.
 #[openapi(tag = "Database")]
 #[delete("/database/<instance>")]
 pub async fn route_delete_database(
     instance: &str,
-    context: &State<crate::context::Context>,
-    _key: ApiKey,
-) -> Status {
-    info!("Deleting moonscale instance {}", instance);
-    let delete_result = delete_database(instance, context).await;
-
-    if delete_result.is_err() {
-        // TODO: Refactor
-        if delete_result
-            .err()
-            .unwrap()
-            .to_string()
-            .contains("No resources found")
-        {
-            return Status::NotFound;
-        }
-        return Status::InternalServerError;
-    }
+    context: &State<Context>,
+    key: ApiKey,
+    trace: RequestTrace,
+) -> Result<status::Custom<Json<OperationModel>>, Status> {
+    let started = Instant::now();
+    let response = delete_accessible_database(instance, &key, context)
+        .with_context(trace.context())
+        .await
+        .map(|operation| status::Custom(Status::Accepted, Json(operation)));
+    let status = match &response {
+        Ok(accepted) => accepted.0,
+        Err(status) => *status,
+    };
 
-    Status::Ok
+    match status.code {
+        202 => {}
+        404 => metrics()
+            .database_deletes
+            .with_label_values(&["not_found"])
+            .inc(),
+        _ => metrics()
+            .database_deletes
+            .with_label_values(&["failure"])
+            .inc(),
+    }
+    context
+        .audit
+        .record(
+            &context.kubernetes_client,
+            audit_record(
+                &key.principal,
+                AuditAction::Delete,
+                "DELETE /api/database/<instance>",
+                instance,
+                status,
+                started,
+            ),
+        )
+        .await;
+    response
 }
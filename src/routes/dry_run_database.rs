@@ -0,0 +1,30 @@
+use crate::dry_run::dry_run_database;
+use crate::middlewares::authentication::ApiKey;
+use crate::models::database::{CreateDatabaseRequestModel, DryRunDatabaseResponseModel};
+use rocket::{post, serde::json::Json, State};
+use rocket_okapi::openapi;
+
+/// # Dry run the creation of a database
+///
+/// This route renders the database template for the request and server-side
+/// applies every manifest with `dryRun=All`, without provisioning anything. It
+/// returns the rendered manifests along with every error found.
+#[openapi(tag = "Database")]
+#[post("/database:dryRun", data = "<request>")]
+pub async fn route_dry_run_database(
+    context: &State<crate::context::Context>,
+    request: Json<CreateDatabaseRequestModel>,
+    key: ApiKey,
+) -> Json<DryRunDatabaseResponseModel> {
+    Json(
+        dry_run_database(
+            &context.config,
+            &context.kubernetes_client,
+            &context.discovery,
+            &context.database_template_yaml_raw,
+            &request,
+            &key.principal,
+        )
+        .await,
+    )
+}
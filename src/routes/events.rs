@@ -0,0 +1,72 @@
+use crate::{middlewares::authentication::ApiKey, models::lifecycle::LifecycleEventModel};
+use k8s_openapi::serde_json;
+use rocket::{
+    get,
+    request::{self, FromRequest, Outcome},
+    response::stream::{Event, EventStream},
+    tokio::{select, sync::broadcast::error::RecvError},
+    Request, Shutdown, State,
+};
+
+/// The `Last-Event-ID` header sent by `EventSource` clients when reconnecting.
+pub struct LastEventId(Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(LastEventId(
+            request
+                .headers()
+                .get_one("Last-Event-ID")
+                .and_then(|id| id.parse().ok()),
+        ))
+    }
+}
+
+fn sse_event(event: &LifecycleEventModel) -> Event {
+    Event::json(event)
+        .id(event.id.to_string())
+        .event(event.event.as_str())
+}
+
+/// # Stream instance lifecycle events
+///
+/// This route streams the lifecycle changes of the instances owned by the caller
+/// as Server-Sent Events. Reconnecting with `Last-Event-ID` replays the events
+/// missed in between, as long as they are still buffered. A `reset` event is sent
+/// when the stream fell behind and dropped events, clients should list the
+/// databases again.
+#[get("/database/events")]
+pub async fn route_database_events(
+    context: &State<crate::context::Context>,
+    key: ApiKey,
+    last_event_id: LastEventId,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let (replay, mut receiver) = context.lifecycle.subscribe(last_event_id.0);
+
+    EventStream! {
+        for event in replay.iter().filter(|event| key.can_access(event.owner.as_deref())) {
+            yield sse_event(event);
+        }
+        loop {
+            let event = select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(missed)) => {
+                        yield Event::json(&serde_json::json!({ "missed": missed })).event("reset");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if key.can_access(event.owner.as_deref()) {
+                yield sse_event(&event);
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Instant, SystemTime};
+
+use crate::{
+    context::Context,
+    kubernetes::{
+        get_database_password, instance_created_at, instance_expires_at, instance_owner,
+        instance_paused_at, instance_phase, instance_size, kubernetes_get_resource,
+    },
+    metadata::InstanceMetadata,
+    metrics::metrics,
+    middlewares::authentication::ApiKey,
+    models::database::{
+        DatabaseInstanceModel, DatabasePhase, GetDatabaseResponseModel, InstanceResourceModel,
+        ResourcePreset,
+    },
+    profiles::DEFAULT_PROFILE,
+    proxy::instance_api_url,
+    template::rendered_instance_resources,
+};
+use k8s_openapi::api::apps::v1::StatefulSet;
+use kube::Api;
+use log::error;
+use rocket::futures::future::join_all;
+use rocket::{get, http::Status, serde::json::Json, State};
+use rocket_okapi::openapi;
+
+fn format_timestamp(timestamp: SystemTime) -> String {
+    humantime::format_rfc3339(timestamp).to_string()
+}
+
+/// Check which of the resources rendered for the instance currently exist.
+async fn instance_inventory(
+    context: &Context,
+    instance: &str,
+) -> Result<Vec<InstanceResourceModel>, anyhow::Error> {
+    let resources = rendered_instance_resources(
+        &context.config,
+        &context.database_template_yaml_raw,
+        instance,
+    )?;
+    let lookups = join_all(resources.iter().map(|resource| {
+        kubernetes_get_resource(&context.kubernetes_client, &context.discovery, resource)
+    }))
+    .await;
+
+    resources
+        .into_iter()
+        .zip(lookups)
+        .map(|(resource, lookup)| {
+            Ok(InstanceResourceModel {
+                kind: resource.gvk.kind,
+                name: resource.name,
+                present: lookup?.is_some(),
+            })
+        })
+        .collect()
+}
+
+/// # Get a managed database
+///
+/// This route returns a single moonscale database, along with its status, expiry,
+/// size, profile, preset and the inventory of its resources.
+#[openapi(tag = "Database")]
+#[get("/database/<instance>")]
+pub async fn route_get_database(
+    instance: &str,
+    context: &State<Context>,
+    key: ApiKey,
+) -> Result<Json<GetDatabaseResponseModel>, Status> {
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let sts = api_sts
+        .get_opt(&format!("moonscale-instance-{}", instance))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "StatefulSet", started);
+    let sts = match sts {
+        Ok(Some(sts)) => sts,
+        Ok(None) => return Err(Status::NotFound),
+        Err(err) => {
+            error!("Failed to get instance {}: {}", instance, err);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    // Instances of other principals are reported as missing rather than forbidden
+    if !key.can_access(instance_owner(&sts).as_deref()) {
+        return Err(Status::NotFound);
+    }
+
+    let root_password = get_database_password(&context.kubernetes_client, instance)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let resources = instance_inventory(context, instance).await.map_err(|err| {
+        error!(
+            "Failed to list the resources of instance {}: {}",
+            instance, err
+        );
+        Status::InternalServerError
+    })?;
+
+    let metadata = InstanceMetadata::from_object(&sts.metadata);
+    let mut instance_model = DatabaseInstanceModel::new(
+        instance,
+        root_password,
+        instance_api_url(&context.config, instance),
+    );
+
+    instance_model.labels = metadata.labels;
+    instance_model.annotations = metadata.annotations;
+    Ok(Json(GetDatabaseResponseModel {
+        instance: instance_model,
+        phase: instance_phase(&sts),
+        owner: instance_owner(&sts),
+        created_at: instance_created_at(&sts).map(format_timestamp),
+        expires_at: instance_expires_at(&sts).map(format_timestamp),
+        paused_at: instance_paused_at(&sts)
+            .filter(|_| instance_phase(&sts) == DatabasePhase::Paused)
+            .map(format_timestamp),
+        size: instance_size(&sts),
+        profile: sts
+            .metadata
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get("moonscale/profile"))
+            .cloned()
+            .unwrap_or(DEFAULT_PROFILE.to_owned()),
+        preset: sts
+            .metadata
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get("moonscale/preset"))
+            .and_then(|preset| ResourcePreset::from_name(preset)),
+        resources,
+    }))
+}
@@ -0,0 +1,442 @@
+use std::{collections::BTreeMap, time::Instant};
+
+use crate::audit::audit_record;
+use crate::context::Context;
+use crate::crypto::constant_time_eq;
+use crate::kubernetes::{instance_exists, instance_owner};
+use crate::metrics::metrics;
+use crate::middlewares::{authentication::ApiKey, tracing::RequestTrace};
+use crate::models::{
+    audit::AuditAction,
+    database::CreateDatabaseRequestModel,
+    integration::{IntegrationAction, IntegrationResponseModel},
+};
+use crate::routes::{
+    create_database::start_create_database, delete_database::start_delete_database,
+};
+use crate::template::MAX_INSTANCE_NAME_LENGTH;
+use hmac::{Hmac, Mac};
+use k8s_openapi::{api::apps::v1::StatefulSet, serde_json};
+use kube::Api;
+use log::{error, info, warn};
+use opentelemetry::trace::FutureExt;
+use rocket::{
+    data::{Data, ToByteUnit},
+    http::Status,
+    post,
+    request::{self, FromRequest, Outcome},
+    serde::json::Json,
+    Request, State,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Pull request payloads embed the whole repository description and can get large.
+const PAYLOAD_LIMIT_MIB: u64 = 5;
+
+/// The headers of the GitHub and GitLab webhooks we rely on.
+pub struct IntegrationHeaders {
+    event: Option<String>,
+    signature: Option<String>,
+    token: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IntegrationHeaders {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let header = |name: &str| request.headers().get_one(name).map(str::to_owned);
+
+        Outcome::Success(IntegrationHeaders {
+            event: header("X-GitHub-Event").or_else(|| header("X-Gitlab-Event")),
+            signature: header("X-Hub-Signature-256"),
+            token: header("X-Gitlab-Token"),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct GithubPullRequestEvent {
+    action: String,
+    number: u64,
+    repository: GithubRepository,
+}
+
+#[derive(Deserialize)]
+struct GithubRepository {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct GitlabMergeRequestEvent {
+    object_attributes: GitlabMergeRequest,
+    project: GitlabProject,
+}
+
+#[derive(Deserialize)]
+struct GitlabMergeRequest {
+    iid: u64,
+    action: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitlabProject {
+    path_with_namespace: String,
+}
+
+enum PullRequestChange {
+    Opened,
+    Closed,
+}
+
+/// The instance name of a pull request, eg: `acme-my-app-pr-42` for `acme/My_App` #42.
+/// Names too long to fit are truncated and suffixed with a hash of the repository,
+/// so two repositories never share an instance.
+fn pull_request_instance_name(repository: &str, number: u64) -> String {
+    let suffix = format!("-pr-{}", number);
+    let repository = repository.to_lowercase();
+    let slug = repository
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    let max_length = MAX_INSTANCE_NAME_LENGTH.saturating_sub(suffix.len());
+
+    if !slug.is_empty() && slug.len() <= max_length {
+        return format!("{}{}", slug, suffix);
+    }
+    let hash = hex::encode(&Sha256::digest(repository.as_bytes())[..4]);
+    let mut prefix: String = slug
+        .chars()
+        .take(max_length.saturating_sub(hash.len() + 1))
+        .collect();
+
+    prefix.truncate(prefix.trim_end_matches('-').len());
+    if prefix.is_empty() {
+        return format!("{}{}", hash, suffix);
+    }
+    format!("{}-{}{}", prefix, hash, suffix)
+}
+
+fn verify_github_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> bool {
+    let Some(signature) = signature
+        .and_then(|signature| signature.strip_prefix("sha256="))
+        .and_then(|signature| hex::decode(signature).ok())
+    else {
+        return false;
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn verify_gitlab_token(expected_token: &str, token: Option<&str>) -> bool {
+    token.is_some_and(|token| constant_time_eq(token.as_bytes(), expected_token.as_bytes()))
+}
+
+async fn read_payload(data: Data<'_>) -> Result<Vec<u8>, Status> {
+    let payload = data
+        .open(PAYLOAD_LIMIT_MIB.mebibytes())
+        .into_bytes()
+        .await
+        .map_err(|_| Status::BadRequest)?;
+
+    if !payload.is_complete() {
+        return Err(Status::PayloadTooLarge);
+    }
+    Ok(payload.into_inner())
+}
+
+fn ignored(instance: Option<String>, reason: &str) -> Json<IntegrationResponseModel> {
+    Json(IntegrationResponseModel {
+        action: IntegrationAction::Ignored,
+        instance,
+        operation_id: None,
+        reason: Some(reason.to_owned()),
+    })
+}
+
+/// Create or delete the database of the pull request, recording the change in the
+/// audit log under the integration principal.
+async fn apply_pull_request_change(
+    context: &Context,
+    principal: &str,
+    route: &str,
+    instance: String,
+    change: PullRequestChange,
+) -> Result<Json<IntegrationResponseModel>, Status> {
+    let started = Instant::now();
+    let (action, integration_action, result) = match change {
+        PullRequestChange::Opened => {
+            match instance_exists(&context.kubernetes_client, &instance).await {
+                Ok(true) => return Ok(ignored(Some(instance), "The database already exists")),
+                Ok(false) => {}
+                Err(err) => {
+                    error!("Failed to check if instance {} exists: {}", instance, err);
+                    return Err(Status::InternalServerError);
+                }
+            }
+            let request = CreateDatabaseRequestModel {
+                name: instance.clone(),
+                size: None,
+                profile: None,
+                preset: None,
+                labels: BTreeMap::new(),
+                annotations: BTreeMap::new(),
+                ttl: None,
+                auto_pause: None,
+            };
+            let key = ApiKey {
+                principal: principal.to_owned(),
+                admin: false,
+            };
+            let result = start_create_database(context, &request, &key)
+                .await
+                .map(|created| (created.0, created.1.operation_id.clone()))
+                .map_err(|(status, error)| {
+                    warn!(
+                        instance = instance.as_str();
+                        "{} couldn't create instance {}: {}", principal, instance, error.message
+                    );
+                    status
+                });
+
+            (AuditAction::Create, IntegrationAction::Created, result)
+        }
+        PullRequestChange::Closed => {
+            let api_sts: Api<StatefulSet> =
+                Api::default_namespaced(context.kubernetes_client.clone());
+            let get_started = Instant::now();
+            let sts = api_sts
+                .get_opt(&format!("moonscale-instance-{}", instance))
+                .await;
+
+            metrics().observe_kubernetes_call("get", "StatefulSet", get_started);
+            match sts {
+                Ok(Some(sts)) if instance_owner(&sts).as_deref() == Some(principal) => {}
+                Ok(Some(_)) => {
+                    return Ok(ignored(
+                        Some(instance),
+                        "The database wasn't created by the integration",
+                    ))
+                }
+                Ok(None) => return Ok(ignored(Some(instance), "The database doesn't exist")),
+                Err(err) => {
+                    error!("Failed to get instance {}: {}", instance, err);
+                    return Err(Status::InternalServerError);
+                }
+            }
+            let result = start_delete_database(&instance, principal, context)
+                .await
+                .map(|operation| (Status::Accepted, operation.id));
+
+            if result.as_ref().err() == Some(&Status::NotFound) {
+                return Ok(ignored(Some(instance), "The database doesn't exist"));
+            }
+            (AuditAction::Delete, IntegrationAction::Deleted, result)
+        }
+    };
+    let status = match &result {
+        Ok((status, _)) => *status,
+        Err(status) => *status,
+    };
+
+    context
+        .audit
+        .record(
+            &context.kubernetes_client,
+            audit_record(principal, action, route, &instance, status, started),
+        )
+        .await;
+    let (_, operation_id) = result?;
+
+    info!(
+        instance = instance.as_str();
+        "{} started {} of instance {}", principal, action.as_str(), instance
+    );
+    Ok(Json(IntegrationResponseModel {
+        action: integration_action,
+        instance: Some(instance),
+        operation_id: Some(operation_id),
+        reason: None,
+    }))
+}
+
+/// # GitHub pull request webhook
+///
+/// Creates a database when a pull request is opened or updated, and deletes it once
+/// the pull request is closed or merged. Payloads must be signed with the secret
+/// configured in `MOONSCALE_GITHUB_WEBHOOK_SECRET`.
+#[post("/integrations/github", data = "<data>")]
+pub async fn route_github_integration(
+    context: &State<Context>,
+    headers: IntegrationHeaders,
+    data: Data<'_>,
+    trace: RequestTrace,
+) -> Result<Json<IntegrationResponseModel>, Status> {
+    let secret = context
+        .config
+        .github_webhook_secret
+        .as_deref()
+        .ok_or(Status::NotFound)?;
+    let payload = read_payload(data).await?;
+
+    if !verify_github_signature(secret, headers.signature.as_deref(), &payload) {
+        warn!("Rejected GitHub webhook with an invalid signature");
+        return Err(Status::Unauthorized);
+    }
+    if headers.event.as_deref() != Some("pull_request") {
+        return Ok(ignored(None, "Not a pull request event"));
+    }
+
+    let event: GithubPullRequestEvent =
+        serde_json::from_slice(&payload).map_err(|_| Status::BadRequest)?;
+    let instance = pull_request_instance_name(&event.repository.full_name, event.number);
+    let change = match event.action.as_str() {
+        "opened" | "reopened" | "synchronize" => PullRequestChange::Opened,
+        "closed" => PullRequestChange::Closed,
+        _ => return Ok(ignored(Some(instance), "Unhandled pull request action")),
+    };
+
+    apply_pull_request_change(
+        context,
+        "github",
+        "POST /api/integrations/github",
+        instance,
+        change,
+    )
+    .with_context(trace.context())
+    .await
+}
+
+/// # GitLab merge request webhook
+///
+/// Creates a database when a merge request is opened or updated, and deletes it
+/// once the merge request is closed or merged. Requests must carry the token
+/// configured in `MOONSCALE_GITLAB_WEBHOOK_TOKEN`.
+#[post("/integrations/gitlab", data = "<data>")]
+pub async fn route_gitlab_integration(
+    context: &State<Context>,
+    headers: IntegrationHeaders,
+    data: Data<'_>,
+    trace: RequestTrace,
+) -> Result<Json<IntegrationResponseModel>, Status> {
+    let expected_token = context
+        .config
+        .gitlab_webhook_token
+        .as_deref()
+        .ok_or(Status::NotFound)?;
+
+    if !verify_gitlab_token(expected_token, headers.token.as_deref()) {
+        warn!("Rejected GitLab webhook with an invalid token");
+        return Err(Status::Unauthorized);
+    }
+    if headers.event.as_deref() != Some("Merge Request Hook") {
+        return Ok(ignored(None, "Not a merge request event"));
+    }
+
+    let payload = read_payload(data).await?;
+    let event: GitlabMergeRequestEvent =
+        serde_json::from_slice(&payload).map_err(|_| Status::BadRequest)?;
+    let instance = pull_request_instance_name(
+        &event.project.path_with_namespace,
+        event.object_attributes.iid,
+    );
+    let change = match event.object_attributes.action.as_deref() {
+        Some("open" | "reopen" | "update") => PullRequestChange::Opened,
+        Some("close" | "merge") => PullRequestChange::Closed,
+        _ => return Ok(ignored(Some(instance), "Unhandled merge request action")),
+    };
+
+    apply_pull_request_change(
+        context,
+        "gitlab",
+        "POST /api/integrations/gitlab",
+        instance,
+        change,
+    )
+    .with_context(trace.context())
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn github_signature(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn names_pull_request_instances() {
+        assert_eq!(
+            pull_request_instance_name("acme/My_App", 42),
+            "acme-my-app-pr-42"
+        );
+        assert_eq!(
+            pull_request_instance_name("Acme/app.js", 1),
+            "acme-app-js-pr-1"
+        );
+    }
+
+    #[test]
+    fn hashes_long_repository_names() {
+        let repository = "acme-corporation/an-application-with-a-very-long-name";
+        let name = pull_request_instance_name(repository, 12345);
+        let hash = hex::encode(&Sha256::digest(repository.as_bytes())[..4]);
+
+        assert!(name.len() <= MAX_INSTANCE_NAME_LENGTH, "{}", name);
+        assert!(name.ends_with(&format!("-{}-pr-12345", hash)), "{}", name);
+        assert!(name.starts_with("acme-corporation-"), "{}", name);
+        // Repositories sharing the truncated prefix still get distinct names
+        assert_ne!(
+            name,
+            pull_request_instance_name(
+                "acme-corporation/an-application-with-a-very-long-name-2",
+                12345
+            )
+        );
+    }
+
+    #[test]
+    fn hashes_repository_names_without_alphanumeric_characters() {
+        let name = pull_request_instance_name("___", 7);
+
+        assert_eq!(
+            name,
+            format!("{}-pr-7", hex::encode(&Sha256::digest(b"___")[..4]))
+        );
+    }
+
+    #[test]
+    fn verifies_github_signatures() {
+        let body = br#"{"action":"opened"}"#;
+        let signature = github_signature("secret", body);
+
+        assert!(verify_github_signature("secret", Some(&signature), body));
+        assert!(!verify_github_signature("other", Some(&signature), body));
+        assert!(!verify_github_signature("secret", Some(&signature), b"{}"));
+        assert!(!verify_github_signature(
+            "secret",
+            Some(signature.trim_start_matches("sha256=")),
+            body
+        ));
+        assert!(!verify_github_signature("secret", Some("sha256=zz"), body));
+        assert!(!verify_github_signature("secret", None, body));
+    }
+
+    #[test]
+    fn verifies_gitlab_tokens() {
+        assert!(verify_gitlab_token("token", Some("token")));
+        assert!(!verify_gitlab_token("token", Some("tokem")));
+        assert!(!verify_gitlab_token("token", Some("token2")));
+        assert!(!verify_gitlab_token("token", Some("")));
+        assert!(!verify_gitlab_token("token", None));
+    }
+}
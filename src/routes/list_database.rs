@@ -1,77 +1,219 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    time::{Instant, SystemTime},
+};
+
 use crate::{
-    kubernetes::get_database_password,
+    kubernetes::{instance_created_at, instance_expires_at, instance_owner, instance_phase},
+    metadata::InstanceMetadata,
+    metrics::metrics,
     middlewares::authentication::ApiKey,
-    models::database::{DatabaseInstanceModel, ListDatabaseResponseModel},
+    models::database::{DatabaseSummaryModel, ListDatabaseQueryModel, ListDatabaseResponseModel},
+    proxy::instance_api_url,
 };
-use k8s_openapi::api::apps::v1::StatefulSet;
+use k8s_openapi::api::{apps::v1::StatefulSet, core::v1::Secret};
 use kube::{api::ListParams, Api};
-use log::{debug, info};
+use log::{debug, error};
 use rocket::{get, http::Status, response::status, serde::json::Json, State};
 use rocket_okapi::openapi;
 
+const MANAGED_SELECTOR: &str = "app.kubernetes.io/managed-by=Moonscale";
+
+/// Root passwords of every managed instance, read with a single list call.
+async fn instance_passwords(
+    kubeclient: &kube::Client,
+) -> Result<HashMap<String, String>, kube::Error> {
+    let api_secrets: Api<Secret> = Api::default_namespaced(kubeclient.clone());
+    let started = Instant::now();
+    let secrets = api_secrets
+        .list(&ListParams::default().labels(MANAGED_SELECTOR))
+        .await;
+
+    metrics().observe_kubernetes_call("list", "Secret", started);
+    Ok(secrets?
+        .items
+        .into_iter()
+        .filter_map(|secret| {
+            let name = secret.metadata.name?;
+            let password = secret.data?.remove("mysql-root-password")?;
+
+            Some((name, String::from_utf8(password.0).ok()?))
+        })
+        .collect())
+}
+
+fn sort_key_ordering(sort: &str, a: &(String, StatefulSet), b: &(String, StatefulSet)) -> Ordering {
+    match sort {
+        "createdAt" => instance_created_at(&a.1).cmp(&instance_created_at(&b.1)),
+        "expiresAt" => instance_expires_at(&a.1).cmp(&instance_expires_at(&b.1)),
+        _ => a.0.cmp(&b.0),
+    }
+}
+
 /// # List all managed databases
 ///
-/// This route is used to list deployed moonscale databases.
+/// This route is used to list deployed moonscale databases, one page at a time.
+/// Keys that aren't admin only see the instances they own, and can't include
+/// the credentials. Filters other than `owner` and `label` apply within the page,
+/// sorting is only available when listing every instance at once.
 #[openapi(tag = "Database")]
-#[get("/database")]
+#[get("/database?<query..>")]
 pub async fn route_list_database(
+    query: ListDatabaseQueryModel,
     context: &State<crate::context::Context>,
-    _key: ApiKey,
+    key: ApiKey,
 ) -> Result<status::Custom<Json<ListDatabaseResponseModel>>, Status> {
+    let expiring_before = match query
+        .expiring_before
+        .as_deref()
+        .map(humantime::parse_rfc3339_weak)
+    {
+        Some(Err(_)) => return Err(Status::BadRequest),
+        Some(Ok(expiring_before)) => Some(expiring_before),
+        None => None,
+    };
+    // Kubernetes pages by name, sorting a page wouldn't sort the whole list
+    if query.sort.is_some() && (query.limit.is_some() || query.continue_token.is_some()) {
+        return Err(Status::BadRequest);
+    }
+    if query.include_credentials == Some(true) && !key.admin {
+        return Err(Status::Forbidden);
+    }
+    let mut selector = vec![MANAGED_SELECTOR.to_owned()];
+
+    if !key.admin {
+        selector.push(format!("moonscale/owner={}", key.principal));
+    }
+    if let Some(owner) = &query.owner {
+        selector.push(format!("moonscale/owner={}", owner));
+    }
+    if let Some(label) = &query.label {
+        selector.push(label.clone());
+    }
+
+    let mut list_params = ListParams::default().labels(&selector.join(","));
+
+    if let Some(limit) = query.limit {
+        list_params = list_params.limit(limit);
+    }
+    if let Some(continue_token) = &query.continue_token {
+        list_params = list_params.continue_token(continue_token);
+    }
+
     // TODO: Refactor, ideally we should create a custom CRD to track the resources we created
     // this would also simplify the "expiration" logic as the controller would just need to delete the CRD
     // and all dependent resources would be cascade deleted
     // but for right now, this will do.
-    let api_sts: Api<StatefulSet> = Api::namespaced(context.kubernetes_client.clone(), "moonscale"); // TODO: Variabilize namespace
-    let managed_sts = api_sts
-        .list(&ListParams::default().labels("app.kubernetes.io/managed-by=Moonscale"))
-        .await;
-    let mut managed_dbs = Vec::<DatabaseInstanceModel>::new();
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let managed_sts = api_sts.list(&list_params).await;
 
-    if managed_sts.is_err() {
-        return Err(Status::InternalServerError);
-    }
-    for sts in managed_sts.unwrap().items {
-        let database_name = sts.metadata.name.clone();
+    metrics().observe_kubernetes_call("list", "StatefulSet", started);
+    let managed_sts = match managed_sts {
+        Ok(managed_sts) => managed_sts,
+        // An invalid label selector or an expired continue token
+        Err(kube::Error::Api(response)) if response.code == 400 || response.code == 410 => {
+            return Err(Status::BadRequest)
+        }
+        Err(err) => {
+            error!("Failed to list managed instances: {}", err);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let mut instances: Vec<(String, StatefulSet)> = managed_sts
+        .items
+        .into_iter()
+        .filter_map(|sts| {
+            let instance_name = sts
+                .metadata
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get("app.kubernetes.io/instance"))
+                .cloned();
 
-        info!("Found managed database: {:?}", database_name);
-        // Note: unwrapping the labels is safe here because managed_sts filtered by label.
-        let db_instance_labels = sts.metadata.labels.clone().unwrap();
-        let db_instance_name = db_instance_labels.get("app.kubernetes.io/instance");
+            if instance_name.is_none() {
+                debug!(
+                    "Found managed database without instance label: {:?}",
+                    sts.metadata.name
+                );
+            }
+            instance_name.map(|instance_name| (instance_name, sts))
+        })
+        .filter(|(name, _)| {
+            query
+                .prefix
+                .as_deref()
+                .is_none_or(|prefix| name.starts_with(prefix))
+        })
+        .filter(|(_, sts)| {
+            query
+                .phase
+                .as_deref()
+                .is_none_or(|phase| instance_phase(sts).as_str() == phase)
+        })
+        .filter(|(_, sts)| {
+            expiring_before.is_none_or(|before| {
+                instance_expires_at(sts).is_some_and(|expires_at| expires_at < before)
+            })
+        })
+        .collect();
 
-        for (key, value) in db_instance_labels.iter() {
-            debug!("Label: {} => {}", key, value);
-        }
+    if let Some(sort) = query.sort.as_deref() {
+        let (descending, sort) = match sort.strip_prefix('-') {
+            Some(sort) => (true, sort),
+            None => (false, sort),
+        };
 
-        if db_instance_name.is_none() {
-            debug!(
-                "Found managed database without instance label: {:?}",
-                database_name
-            );
-            continue;
-        }
+        instances.sort_by(|a, b| {
+            let ordering = sort_key_ordering(sort, a, b);
 
-        let db_root_password =
-            get_database_password(&context.kubernetes_client, db_instance_name.unwrap()).await;
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
 
-        if db_root_password.is_err() {
-            debug!(
-                "Failed to get database password for: {:?}",
-                db_instance_name.unwrap()
-            );
-            continue;
-        }
-        managed_dbs.push(DatabaseInstanceModel {
-            planetscale_api_url: format!(
-                "https://moonscale-instance-{}.{}",
-                db_instance_name.unwrap(),
-                context.config.ingress_domain
-            ),
+    let passwords = match query.include_credentials {
+        Some(true) => instance_passwords(&context.kubernetes_client)
+            .await
+            .map_err(|err| {
+                error!(
+                    "Failed to read the credentials of managed instances: {}",
+                    err
+                );
+                Status::InternalServerError
+            })?,
+        _ => HashMap::new(),
+    };
+    let format_timestamp = |timestamp: SystemTime| humantime::format_rfc3339(timestamp).to_string();
+    let items = instances
+        .into_iter()
+        .map(|(name, sts)| DatabaseSummaryModel {
+            planetscale_api_url: instance_api_url(&context.config, &name),
             database_username: "root".to_owned(),
-            database_password: db_root_password.unwrap(),
-            database_name: db_instance_name.unwrap().to_string(),
+            database_password: passwords
+                .get(&format!("moonscale-instance-{}", name))
+                .cloned(),
+            phase: instance_phase(&sts),
+            owner: instance_owner(&sts),
+            created_at: instance_created_at(&sts).map(format_timestamp),
+            expires_at: instance_expires_at(&sts).map(format_timestamp),
+            labels: InstanceMetadata::from_object(&sts.metadata).labels,
+            database_name: name,
         })
-    }
+        .collect();
 
-    Ok(status::Custom(Status::Ok, Json(managed_dbs)))
+    Ok(status::Custom(
+        Status::Ok,
+        Json(ListDatabaseResponseModel {
+            items,
+            continue_token: managed_sts
+                .metadata
+                .continue_
+                .filter(|continue_token| !continue_token.is_empty()),
+        }),
+    ))
 }
@@ -0,0 +1,45 @@
+use std::time::Instant;
+
+use crate::{kubernetes::instance_phase, metrics::metrics, models::database::DatabasePhase};
+use k8s_openapi::api::apps::v1::StatefulSet;
+use kube::{api::ListParams, Api};
+use log::warn;
+use rocket::{get, http::ContentType, State};
+
+/// Refresh the managed instances gauge, this is done on scrape so the value is
+/// always in sync with the cluster.
+async fn refresh_managed_instances(context: &crate::context::Context) {
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let managed_sts = api_sts
+        .list(&ListParams::default().labels("app.kubernetes.io/managed-by=Moonscale"))
+        .await;
+
+    metrics().observe_kubernetes_call("list", "StatefulSet", started);
+    match managed_sts {
+        Ok(managed_sts) => {
+            for phase in DatabasePhase::ALL {
+                let count = managed_sts
+                    .items
+                    .iter()
+                    .filter(|sts| instance_phase(sts) == phase)
+                    .count();
+
+                metrics()
+                    .managed_instances
+                    .with_label_values(&[phase.as_str()])
+                    .set(count as i64);
+            }
+        }
+        Err(err) => warn!("Failed to list managed instances for metrics: {}", err),
+    }
+}
+
+/// # Prometheus metrics
+///
+/// Exposes moonscale metrics in the Prometheus text format.
+#[get("/metrics")]
+pub async fn route_metrics(context: &State<crate::context::Context>) -> (ContentType, String) {
+    refresh_managed_instances(context).await;
+    (ContentType::Plain, metrics().render())
+}
@@ -1,3 +1,17 @@
+pub mod activator;
+pub mod audit;
 pub mod create_database;
 pub mod delete_database;
+pub mod dry_run_database;
+pub mod events;
+pub mod get_database;
+pub mod integrations;
 pub mod list_database;
+pub mod metrics;
+pub mod operations;
+pub mod pause_database;
+pub mod probes;
+pub mod proxy;
+pub mod quota;
+pub mod resize_database;
+pub mod webhooks;
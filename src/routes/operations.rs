@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use crate::{
+    middlewares::authentication::ApiKey,
+    models::operation::{ListOperationsResponseModel, OperationModel},
+    operations::OperationFilter,
+};
+use rocket::{get, http::Status, serde::json::Json, State};
+use rocket_okapi::openapi;
+
+/// Upper bound of the long-polling `wait` parameter, in seconds.
+const MAX_WAIT_SECONDS: u64 = 60;
+
+/// # List operations
+///
+/// This route returns the long running operations, newest first. Every filter is
+/// optional, finished operations are pruned after the configured retention. Only
+/// admin keys see the operations started by other principals.
+#[openapi(tag = "Operations")]
+#[get("/operations?<instance>&<type>&<state>&<limit>")]
+pub async fn route_list_operations(
+    instance: Option<&str>,
+    r#type: Option<&str>,
+    state: Option<&str>,
+    limit: Option<usize>,
+    context: &State<crate::context::Context>,
+    key: ApiKey,
+) -> Json<ListOperationsResponseModel> {
+    Json(context.operations.list(&OperationFilter {
+        principal: (!key.admin).then_some(key.principal.as_str()),
+        instance,
+        operation_type: r#type,
+        state,
+        limit,
+    }))
+}
+
+/// # Get an operation
+///
+/// This route returns the progress of a long running operation, eg: the deletion
+/// of a database started by `DELETE /api/database/<instance>`. When `wait` is set,
+/// the call blocks until the operation completes or `wait` seconds (at most 60)
+/// elapsed, whichever comes first.
+#[openapi(tag = "Operations")]
+#[get("/operations/<id>?<wait>")]
+pub async fn route_get_operation(
+    id: &str,
+    wait: Option<u64>,
+    context: &State<crate::context::Context>,
+    key: ApiKey,
+) -> Result<Json<OperationModel>, Status> {
+    // Operations of other principals are hidden as if they didn't exist
+    if context
+        .operations
+        .get(id)
+        .is_some_and(|operation| !key.admin && operation.principal != key.principal)
+    {
+        return Err(Status::NotFound);
+    }
+    let operation = match wait {
+        Some(wait) => {
+            context
+                .operations
+                .wait(id, Duration::from_secs(wait.min(MAX_WAIT_SECONDS)))
+                .await
+        }
+        None => context.operations.get(id),
+    };
+
+    operation.map(Json).ok_or(Status::NotFound)
+}
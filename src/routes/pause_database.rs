@@ -0,0 +1,256 @@
+use std::time::{Duration, Instant};
+
+use crate::activator::switch_ingress;
+use crate::audit::audit_record;
+use crate::context::Context;
+use crate::kubernetes::{
+    instance_owner, instance_phase, kubernetes_scale_instance, publish_lifecycle_event,
+    wait_for_instance_ready, wait_for_instance_stopped, InstanceEvent,
+};
+use crate::metrics::metrics;
+use crate::middlewares::{authentication::ApiKey, tracing::RequestTrace};
+use crate::models::audit::AuditAction;
+use crate::models::database::DatabasePhase;
+use crate::models::error::{error_response, ErrorResponse};
+use crate::models::operation::{
+    OperationModel, OperationType, ResourceProgressModel, ResourceState,
+};
+use k8s_openapi::api::apps::v1::StatefulSet;
+use kube::Api;
+use log::{error, info, warn};
+use opentelemetry::trace::FutureExt;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{http::Status, post, State};
+use rocket_okapi::openapi;
+
+/// How long we wait for the pods of a paused instance to stop.
+const PAUSE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Wait for the pods to follow the new replicas and complete the operation.
+async fn track_scale(context: Context, instance: String, paused: bool, operation_id: String) {
+    let result = match paused {
+        true => {
+            wait_for_instance_stopped(&context.kubernetes_client, &instance, PAUSE_TIMEOUT).await
+        }
+        false => wait_for_instance_ready(&context.kubernetes_client, &instance).await,
+    };
+    let error = match result {
+        Ok(true) => None,
+        Ok(false) if paused => Some("The pods didn't stop before the timeout".to_owned()),
+        Ok(false) => Some("The instance didn't become ready before the timeout".to_owned()),
+        Err(err) => Some(format!("Failed to watch the instance: {}", err)),
+    };
+
+    match &error {
+        None => {
+            // Wait for the instance to be ready before sending it traffic again
+            if !paused {
+                switch_ingress(&context, &instance, false).await;
+            }
+            info!(
+                instance = instance.as_str();
+                "Instance {} is {}", instance, if paused { "paused" } else { "resumed" }
+            );
+            let (event, note) = match paused {
+                true => (
+                    InstanceEvent::Paused,
+                    format!("Paused moonscale instance {}", instance),
+                ),
+                false => (
+                    InstanceEvent::Resumed,
+                    format!("Resumed moonscale instance {}", instance),
+                ),
+            };
+
+            publish_lifecycle_event(&context.kubernetes_client, &instance, event, note).await;
+        }
+        Some(err) => warn!(
+            instance = instance.as_str();
+            "Failed to scale instance {}: {}", instance, err
+        ),
+    }
+    context.operations.update(&operation_id, |operation| {
+        operation.resources[0].state = match error {
+            None => ResourceState::Scaled,
+            Some(_) => ResourceState::Failed,
+        };
+        operation.resources[0].error = error.clone();
+    });
+    context.operations.finish(&operation_id, error);
+}
+
+/// Scale the instance down to zero, or back up, and wait for its pods in the
+/// background, returns the operation tracking it.
+pub async fn start_scale_database(
+    instance: &str,
+    paused: bool,
+    key: &ApiKey,
+    context: &Context,
+) -> Result<OperationModel, ErrorResponse> {
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let sts = api_sts
+        .get_opt(&format!("moonscale-instance-{}", instance))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "StatefulSet", started);
+    let not_found = || {
+        error_response(
+            Status::NotFound,
+            "instance_not_found",
+            format!("Instance {} doesn't exist", instance),
+        )
+    };
+    let sts = match sts {
+        Ok(Some(sts)) => sts,
+        Ok(None) => return Err(not_found()),
+        Err(err) => {
+            error!("Failed to get instance {}: {}", instance, err);
+            return Err(error_response(
+                Status::InternalServerError,
+                "scale_failed",
+                "Failed to get the instance",
+            ));
+        }
+    };
+
+    // Instances of other principals are reported as missing rather than forbidden
+    if !key.can_access(instance_owner(&sts).as_deref()) {
+        return Err(not_found());
+    }
+
+    match instance_phase(&sts) {
+        DatabasePhase::Terminating => {
+            return Err(error_response(
+                Status::Conflict,
+                "instance_terminating",
+                format!("Instance {} is being deleted", instance),
+            ))
+        }
+        DatabasePhase::Paused if paused => {
+            return Err(error_response(
+                Status::Conflict,
+                "already_paused",
+                format!("Instance {} is already paused", instance),
+            ))
+        }
+        DatabasePhase::Paused => {}
+        _ if !paused => {
+            return Err(error_response(
+                Status::Conflict,
+                "not_paused",
+                format!("Instance {} isn't paused", instance),
+            ))
+        }
+        _ => {}
+    }
+
+    kubernetes_scale_instance(&context.kubernetes_client, instance, paused)
+        .await
+        .map_err(|err| {
+            error!("Failed to scale instance {}: {}", instance, err);
+            error_response(
+                Status::InternalServerError,
+                "scale_failed",
+                "Failed to scale the instance",
+            )
+        })?;
+    if paused {
+        switch_ingress(context, instance, true).await;
+    }
+    info!(
+        instance = instance;
+        "{} instance {}", if paused { "Pausing" } else { "Resuming" }, instance
+    );
+
+    let operation_type = match paused {
+        true => OperationType::Pause,
+        false => OperationType::Resume,
+    };
+    let operation = context
+        .operations
+        .create(operation_type, instance, &key.principal);
+
+    context.operations.update(&operation.id, |operation| {
+        operation.resources = vec![ResourceProgressModel {
+            kind: "StatefulSet".to_owned(),
+            name: format!("moonscale-instance-{}", instance),
+            state: ResourceState::Scaling,
+            attempts: 1,
+            error: None,
+        }];
+    });
+    rocket::tokio::spawn(
+        track_scale(
+            context.clone(),
+            instance.to_owned(),
+            paused,
+            operation.id.clone(),
+        )
+        .with_current_context(),
+    );
+    Ok(context.operations.get(&operation.id).unwrap_or(operation))
+}
+
+async fn scale_database_response(
+    instance: &str,
+    paused: bool,
+    context: &Context,
+    key: ApiKey,
+    trace: RequestTrace,
+) -> Result<status::Custom<Json<OperationModel>>, ErrorResponse> {
+    let started = Instant::now();
+    let response = start_scale_database(instance, paused, &key, context)
+        .with_context(trace.context())
+        .await
+        .map(|operation| status::Custom(Status::Accepted, Json(operation)));
+    let status = match &response {
+        Ok(accepted) => accepted.0,
+        Err((status, _)) => *status,
+    };
+    let (action, route) = match paused {
+        true => (AuditAction::Pause, "POST /api/database/<instance>/pause"),
+        false => (AuditAction::Resume, "POST /api/database/<instance>/resume"),
+    };
+
+    context
+        .audit
+        .record(
+            &context.kubernetes_client,
+            audit_record(&key.principal, action, route, instance, status, started),
+        )
+        .await;
+    response
+}
+
+/// # Pause a managed database
+///
+/// This route scales a moonscale database down to zero, its volumes and credentials
+/// are kept until it's resumed. Poll `GET /api/operations/<id>` to know when its
+/// pods are stopped.
+#[openapi(tag = "Database")]
+#[post("/database/<instance>/pause")]
+pub async fn route_pause_database(
+    instance: &str,
+    context: &State<Context>,
+    key: ApiKey,
+    trace: RequestTrace,
+) -> Result<status::Custom<Json<OperationModel>>, ErrorResponse> {
+    scale_database_response(instance, true, context, key, trace).await
+}
+
+/// # Resume a paused database
+///
+/// This route scales a paused moonscale database back up. Poll
+/// `GET /api/operations/<id>` to know when it accepts connections again.
+#[openapi(tag = "Database")]
+#[post("/database/<instance>/resume")]
+pub async fn route_resume_database(
+    instance: &str,
+    context: &State<Context>,
+    key: ApiKey,
+    trace: RequestTrace,
+) -> Result<status::Custom<Json<OperationModel>>, ErrorResponse> {
+    scale_database_response(instance, false, context, key, trace).await
+}
@@ -0,0 +1,48 @@
+use crate::models::health::HealthReportModel;
+use rocket::{get, http::Status, response::status, serde::json::Json, State};
+use rocket_okapi::openapi;
+
+/// # Get if service is ready
+///
+/// 200 if service is ready, used for kubernetes probes. The service is ready when
+/// the Kubernetes API is reachable, the configured namespace is accessible and the
+/// database template renders.
+#[openapi(tag = "Kubernetes Probes")]
+#[get("/readyz")]
+pub async fn readyz_route(context: &State<crate::context::Context>) -> Status {
+    match context.health.report(context).await.healthy {
+        true => Status::Ok,
+        false => Status::ServiceUnavailable,
+    }
+}
+
+/// # Get if service is alive
+///
+/// 200 as long as the server is able to answer requests, used for kubernetes probes.
+#[openapi(tag = "Kubernetes Probes")]
+#[get("/livez")]
+pub fn livez_route() -> Status {
+    Status::Ok
+}
+
+/// # Get the service health
+///
+/// Same checks as `/readyz`, pass `verbose` to get the result of each check.
+#[openapi(tag = "Kubernetes Probes")]
+#[get("/healthz?<verbose>")]
+pub async fn healthz_route(
+    verbose: Option<bool>,
+    context: &State<crate::context::Context>,
+) -> status::Custom<Json<HealthReportModel>> {
+    let mut report = context.health.report(context).await;
+    let status = match report.healthy {
+        true => Status::Ok,
+        false => Status::ServiceUnavailable,
+    };
+
+    // `?verbose` without a value is parsed as true
+    if !verbose.unwrap_or(false) {
+        report.checks = None;
+    }
+    status::Custom(status, Json(report))
+}
@@ -0,0 +1,61 @@
+use crate::activator::ProxiedResponse;
+use crate::context::Context;
+use crate::models::error::ErrorResponse;
+use crate::proxy::ProxyMode;
+use crate::routes::activator::{proxy_to_instance, read_proxied_body};
+use log::debug;
+use rocket::data::Data;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome};
+use rocket::{post, Request, State};
+
+/// A request to the built-in proxy in path mode, requests are forwarded to the
+/// regular routes in any other mode.
+pub struct PathProxyRequest {
+    headers: Vec<(String, String)>,
+    query: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PathProxyRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let context = request.rocket().state::<Context>().unwrap();
+
+        match &context.config.proxy {
+            Some(proxy) if proxy.mode == ProxyMode::Path => Outcome::Success(PathProxyRequest {
+                headers: request
+                    .headers()
+                    .iter()
+                    .map(|header| (header.name().to_string(), header.value().to_owned()))
+                    .collect(),
+                query: request.uri().query().map(|query| query.to_string()),
+            }),
+            _ => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+/// Forward a call of the PlanetScale HTTP API to the instance named by the first
+/// segment of the path.
+#[post("/<instance>/psdb.v1alpha1.Database/<method>", data = "<body>")]
+pub async fn route_proxy_database(
+    instance: &str,
+    method: &str,
+    request: PathProxyRequest,
+    body: Data<'_>,
+    context: &State<Context>,
+) -> Result<ProxiedResponse, ErrorResponse> {
+    let body = read_proxied_body(Some(body)).await?;
+    let path = match request.query {
+        Some(query) => format!("/psdb.v1alpha1.Database/{}?{}", method, query),
+        None => format!("/psdb.v1alpha1.Database/{}", method),
+    };
+
+    debug!(
+        instance = instance;
+        "Received POST {} for instance {}", path, instance
+    );
+    proxy_to_instance(context, instance, "POST", &path, request.headers, body).await
+}
@@ -0,0 +1,43 @@
+use crate::{
+    middlewares::authentication::ApiKey,
+    models::quota::{QuotaLimitsModel, QuotaModel, QuotaUsageModel},
+    quantity::format_quantity,
+    quota::quota_usage,
+};
+use log::error;
+use rocket::{get, http::Status, serde::json::Json, State};
+use rocket_okapi::openapi;
+
+/// # Get the quota
+///
+/// This route returns the configured limits, along with the current usage of
+/// the server and of the caller.
+#[openapi(tag = "Quota")]
+#[get("/quota")]
+pub async fn route_get_quota(
+    context: &State<crate::context::Context>,
+    key: ApiKey,
+) -> Result<Json<QuotaModel>, Status> {
+    let usage = quota_usage(&context.kubernetes_client)
+        .await
+        .map_err(|err| {
+            error!("Failed to read the quota usage: {}", err);
+            Status::InternalServerError
+        })?;
+    let limits = &context.config.quota;
+
+    Ok(Json(QuotaModel {
+        principal: key.principal.clone(),
+        limits: QuotaLimitsModel {
+            max_instances: limits.max_instances,
+            max_instances_per_owner: limits.max_instances_per_owner,
+            max_storage: limits.max_storage.map(format_quantity),
+            max_ttl: limits.max_ttl,
+        },
+        usage: QuotaUsageModel {
+            instances: usage.instances(),
+            owner_instances: usage.owner_instances(&key.principal),
+            storage: format_quantity(usage.storage()),
+        },
+    }))
+}
@@ -0,0 +1,432 @@
+use std::time::{Duration, Instant};
+
+use crate::audit::audit_record;
+use crate::context::Context;
+use crate::kubernetes::{
+    instance_owner, instance_size, kubernetes_record_instance_size, kubernetes_resize_volume,
+    volume_expansion_allowed,
+};
+use crate::metrics::metrics;
+use crate::middlewares::{authentication::ApiKey, tracing::RequestTrace};
+use crate::models::audit::AuditAction;
+use crate::models::database::ResizeDatabaseRequestModel;
+use crate::models::error::{error_response, ErrorResponse};
+use crate::models::operation::{
+    OperationModel, OperationType, ResourceProgressModel, ResourceState,
+};
+use crate::profiles::{resolve_profile, DEFAULT_PROFILE};
+use crate::quantity::{format_quantity, parse_quantity};
+use crate::quota::quota_usage;
+use crate::template::rendered_instance_resources;
+use k8s_openapi::api::{apps::v1::StatefulSet, core::v1::PersistentVolumeClaim};
+use kube::Api;
+use log::{error, info, warn};
+use opentelemetry::trace::FutureExt;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::tokio::time::sleep;
+use rocket::{http::Status, patch, State};
+use rocket_okapi::openapi;
+
+/// How long we wait for the volumes to reach their new capacity.
+const RESIZE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+fn internal_error(message: &str) -> ErrorResponse {
+    error_response(Status::InternalServerError, "resize_failed", message)
+}
+
+/// Whether the claim reached the requested capacity, or why it's still pending.
+fn volume_resize_state(pvc: &PersistentVolumeClaim, size: u64) -> Result<(), String> {
+    let status = pvc.status.as_ref();
+    let capacity = status
+        .and_then(|status| status.capacity.as_ref())
+        .and_then(|capacity| capacity.get("storage"))
+        .and_then(|capacity| parse_quantity(&capacity.0).ok())
+        .unwrap_or(0);
+    let pending = status
+        .and_then(|status| status.conditions.as_ref())
+        .into_iter()
+        .flatten()
+        .find(|condition| {
+            condition.status == "True"
+                && (condition.type_ == "Resizing" || condition.type_ == "FileSystemResizePending")
+        });
+
+    match pending {
+        Some(condition) => Err(format!(
+            "{}: {}",
+            condition.type_,
+            condition.message.as_deref().unwrap_or("no details")
+        )),
+        None if capacity < size => Err(format!(
+            "The capacity is still {}",
+            format_quantity(capacity)
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Poll the claims until they reach the requested capacity and complete the operation.
+async fn track_volume_resize(
+    context: Context,
+    instance: String,
+    claims: Vec<String>,
+    size: u64,
+    operation_id: String,
+) {
+    let api: Api<PersistentVolumeClaim> =
+        Api::default_namespaced(context.kubernetes_client.clone());
+    let deadline = Instant::now() + RESIZE_TIMEOUT;
+    let mut pending: Vec<usize> = (0..claims.len()).collect();
+    let mut last_errors = vec![None; claims.len()];
+
+    while !pending.is_empty() && Instant::now() < deadline {
+        sleep(RESIZE_POLL_INTERVAL).await;
+        for index in pending.clone() {
+            let started = Instant::now();
+            let pvc = api.get(&claims[index]).await;
+
+            metrics().observe_kubernetes_call("get", "PersistentVolumeClaim", started);
+            let state = match pvc {
+                Ok(pvc) => volume_resize_state(&pvc, size),
+                Err(err) => Err(format!("Failed to get the claim: {}", err)),
+            };
+
+            if state.is_ok() {
+                pending.retain(|pending| *pending != index);
+            }
+            last_errors[index] = state.as_ref().err().cloned();
+            context.operations.update(&operation_id, |operation| {
+                let progress = &mut operation.resources[index];
+
+                progress.state = match state {
+                    Ok(()) => ResourceState::Resized,
+                    Err(_) => ResourceState::Resizing,
+                };
+                progress.error = state.err();
+            });
+        }
+    }
+
+    if pending.is_empty() {
+        info!(
+            instance = instance.as_str();
+            "Resized the volumes of instance {} to {}", instance, format_quantity(size)
+        );
+        context.operations.finish(&operation_id, None);
+        return;
+    }
+    warn!(
+        instance = instance.as_str();
+        "{} volumes of instance {} didn't reach their new size", pending.len(), instance
+    );
+    context.operations.update(&operation_id, |operation| {
+        for index in &pending {
+            operation.resources[*index].state = ResourceState::Failed;
+            operation.resources[*index].error = last_errors[*index].clone();
+        }
+    });
+    context.operations.finish(
+        &operation_id,
+        Some(format!(
+            "{} volumes didn't reach their new size before the timeout",
+            pending.len()
+        )),
+    );
+}
+
+/// Expand the volumes of the instance and track the resize in the background,
+/// returns the operation tracking it.
+pub async fn start_resize_database(
+    instance: &str,
+    request: &ResizeDatabaseRequestModel,
+    key: &ApiKey,
+    context: &Context,
+) -> Result<OperationModel, ErrorResponse> {
+    let api_sts: Api<StatefulSet> = Api::default_namespaced(context.kubernetes_client.clone());
+    let started = Instant::now();
+    let sts = api_sts
+        .get_opt(&format!("moonscale-instance-{}", instance))
+        .await;
+
+    metrics().observe_kubernetes_call("get", "StatefulSet", started);
+    let not_found = || {
+        error_response(
+            Status::NotFound,
+            "instance_not_found",
+            format!("Instance {} doesn't exist", instance),
+        )
+    };
+    let sts = match sts {
+        Ok(Some(sts)) => sts,
+        Ok(None) => return Err(not_found()),
+        Err(err) => {
+            error!("Failed to get instance {}: {}", instance, err);
+            return Err(internal_error("Failed to get the instance"));
+        }
+    };
+
+    // Instances of other principals are reported as missing rather than forbidden
+    if !key.can_access(instance_owner(&sts).as_deref()) {
+        return Err(not_found());
+    }
+
+    let profile_name = sts
+        .metadata
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get("moonscale/profile"))
+        .map_or(DEFAULT_PROFILE, String::as_str);
+    let profile = resolve_profile(&context.config, Some(profile_name))
+        .map_err(|err| error_response(Status::Conflict, "unknown_profile", err))?;
+    let size = profile
+        .resolve_size(Some(&request.size))
+        .map_err(|err| error_response(Status::BadRequest, "invalid_size", err))?;
+    let current_size = instance_size(&sts)
+        .and_then(|size| parse_quantity(&size).ok())
+        .unwrap_or(0);
+
+    if size < current_size {
+        return Err(error_response(
+            Status::Conflict,
+            "shrink_not_supported",
+            format!(
+                "Volumes can't shrink, the instance already has {}",
+                format_quantity(current_size)
+            ),
+        ));
+    }
+
+    let claims: Vec<String> = rendered_instance_resources(
+        &context.config,
+        &context.database_template_yaml_raw,
+        instance,
+    )
+    .map_err(|err| {
+        error!(
+            "Failed to render resources of instance {}: {:?}",
+            instance, err
+        );
+        internal_error("Failed to render the resources of the instance")
+    })?
+    .into_iter()
+    .filter(|resource| resource.gvk.kind == "PersistentVolumeClaim")
+    .map(|resource| resource.name)
+    .collect();
+    let api_pvc: Api<PersistentVolumeClaim> =
+        Api::default_namespaced(context.kubernetes_client.clone());
+
+    for claim in &claims {
+        let started = Instant::now();
+        let pvc = api_pvc.get_opt(claim).await;
+
+        metrics().observe_kubernetes_call("get", "PersistentVolumeClaim", started);
+        let pvc = match pvc {
+            Ok(Some(pvc)) => pvc,
+            Ok(None) => {
+                return Err(error_response(
+                    Status::Conflict,
+                    "volume_not_found",
+                    format!("Volume {} doesn't exist yet", claim),
+                ))
+            }
+            Err(err) => {
+                error!("Failed to get volume {}: {}", claim, err);
+                return Err(internal_error("Failed to get the volumes of the instance"));
+            }
+        };
+        let expansion_allowed = volume_expansion_allowed(&context.kubernetes_client, &pvc)
+            .await
+            .map_err(|err| {
+                error!("Failed to get the storage class of {}: {}", claim, err);
+                internal_error("Failed to get the storage class of the volumes")
+            })?;
+
+        if !expansion_allowed {
+            return Err(error_response(
+                Status::Conflict,
+                "expansion_not_supported",
+                format!(
+                    "The storage class of volume {} doesn't allow volume expansion",
+                    claim
+                ),
+            ));
+        }
+    }
+
+    let _quota_guard = context.quota_lock.lock().await;
+    let usage = quota_usage(&context.kubernetes_client)
+        .await
+        .map_err(|err| {
+            error!("Failed to read the quota usage: {}", err);
+            internal_error("Failed to read the quota usage")
+        })?;
+
+    context.config.quota.check_storage(&usage, instance, size)?;
+
+    let quantity = format_quantity(size);
+
+    for claim in &claims {
+        kubernetes_resize_volume(&context.kubernetes_client, claim, &quantity)
+            .await
+            .map_err(|err| {
+                error!("Failed to resize volume {}: {}", claim, err);
+                error_response(
+                    Status::UnprocessableEntity,
+                    "resize_rejected",
+                    format!("Kubernetes rejected the resize of {}: {}", claim, err),
+                )
+            })?;
+    }
+    kubernetes_record_instance_size(&context.kubernetes_client, instance, &quantity)
+        .await
+        .map_err(|err| {
+            error!(
+                "Failed to record the size of instance {}: {}",
+                instance, err
+            );
+            internal_error("Failed to record the new size of the instance")
+        })?;
+    info!(
+        instance = instance;
+        "Resizing the volumes of instance {} to {}", instance, quantity
+    );
+
+    let operation = context
+        .operations
+        .create(OperationType::Resize, instance, &key.principal);
+
+    context.operations.update(&operation.id, |operation| {
+        operation.resources = claims
+            .iter()
+            .map(|claim| ResourceProgressModel {
+                kind: "PersistentVolumeClaim".to_owned(),
+                name: claim.clone(),
+                state: ResourceState::Resizing,
+                attempts: 1,
+                error: None,
+            })
+            .collect();
+    });
+    rocket::tokio::spawn(
+        track_volume_resize(
+            context.clone(),
+            instance.to_owned(),
+            claims,
+            size,
+            operation.id.clone(),
+        )
+        .with_current_context(),
+    );
+    Ok(context.operations.get(&operation.id).unwrap_or(operation))
+}
+
+/// # Resize a managed database
+///
+/// This route expands the volumes of a moonscale database in place, when their
+/// storage class allows volume expansion. The resize completes in the background,
+/// poll `GET /api/operations/<id>` to follow its progress.
+#[openapi(tag = "Database")]
+#[patch("/database/<instance>", data = "<request>")]
+pub async fn route_resize_database(
+    instance: &str,
+    request: Json<ResizeDatabaseRequestModel>,
+    context: &State<Context>,
+    key: ApiKey,
+    trace: RequestTrace,
+) -> Result<status::Custom<Json<OperationModel>>, ErrorResponse> {
+    let started = Instant::now();
+    let response = start_resize_database(instance, &request, &key, context)
+        .with_context(trace.context())
+        .await
+        .map(|operation| status::Custom(Status::Accepted, Json(operation)));
+    let status = match &response {
+        Ok(accepted) => accepted.0,
+        Err((status, _)) => *status,
+    };
+
+    context
+        .audit
+        .record(
+            &context.kubernetes_client,
+            audit_record(
+                &key.principal,
+                AuditAction::Resize,
+                "PATCH /api/database/<instance>",
+                instance,
+                status,
+                started,
+            ),
+        )
+        .await;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::{
+        api::core::v1::{PersistentVolumeClaimCondition, PersistentVolumeClaimStatus},
+        apimachinery::pkg::api::resource::Quantity,
+    };
+
+    use super::*;
+
+    fn pvc(capacity: Option<&str>, conditions: &[(&str, &str)]) -> PersistentVolumeClaim {
+        PersistentVolumeClaim {
+            status: Some(PersistentVolumeClaimStatus {
+                capacity: capacity
+                    .map(|capacity| [("storage".to_owned(), Quantity(capacity.to_owned()))].into()),
+                conditions: Some(
+                    conditions
+                        .iter()
+                        .map(|(type_, status)| PersistentVolumeClaimCondition {
+                            type_: type_.to_string(),
+                            status: status.to_string(),
+                            message: Some("Waiting for the node".to_owned()),
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resized_once_the_capacity_is_reached() {
+        assert_eq!(
+            volume_resize_state(&pvc(Some("10Gi"), &[]), 10 << 30),
+            Ok(())
+        );
+        assert_eq!(
+            volume_resize_state(&pvc(Some("20Gi"), &[]), 10 << 30),
+            Ok(())
+        );
+        assert_eq!(
+            volume_resize_state(&pvc(Some("10Gi"), &[("Resizing", "False")]), 10 << 30),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn pending_while_the_capacity_is_lower() {
+        assert_eq!(
+            volume_resize_state(&pvc(Some("5Gi"), &[]), 10 << 30),
+            Err("The capacity is still 5Gi".to_owned())
+        );
+        assert!(volume_resize_state(&pvc(None, &[]), 10 << 30).is_err());
+        assert!(volume_resize_state(&PersistentVolumeClaim::default(), 10 << 30).is_err());
+    }
+
+    #[test]
+    fn pending_while_resizing() {
+        for condition in ["Resizing", "FileSystemResizePending"] {
+            assert_eq!(
+                volume_resize_state(&pvc(Some("10Gi"), &[(condition, "True")]), 10 << 30),
+                Err(format!("{}: Waiting for the node", condition))
+            );
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    middlewares::authentication::ApiKey,
+    models::webhook::{
+        CreateWebhookRequestModel, ListWebhookDeliveriesResponseModel, ListWebhooksResponseModel,
+        WebhookModel,
+    },
+    webhooks::check_webhook_destination,
+};
+use log::{error, warn};
+use rocket::{delete, get, http::Status, post, response::status, serde::json::Json, State};
+use rocket_okapi::openapi;
+
+/// The webhook, if it exists and the caller can manage it.
+fn accessible_webhook(
+    context: &crate::context::Context,
+    key: &ApiKey,
+    id: &str,
+) -> Result<WebhookModel, Status> {
+    context
+        .webhooks
+        .get(id)
+        .filter(|webhook| key.admin || webhook.principal == key.principal)
+        .ok_or(Status::NotFound)
+}
+
+/// # Register a webhook
+///
+/// This route registers a webhook receiving a signed JSON `POST` for each lifecycle
+/// event of the instances the caller can access. Failed deliveries are retried with
+/// an exponential backoff. Only admin principals can register webhooks pointing at
+/// private, loopback or link-local addresses.
+#[openapi(tag = "Webhooks")]
+#[post("/webhooks", data = "<request>")]
+pub async fn route_create_webhook(
+    context: &State<crate::context::Context>,
+    request: Json<CreateWebhookRequestModel>,
+    key: ApiKey,
+) -> Result<status::Custom<Json<WebhookModel>>, Status> {
+    let scheme_valid =
+        reqwest::Url::parse(&request.url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+
+    if !scheme_valid
+        || request.secret.is_empty()
+        || request.events.as_ref().is_some_and(Vec::is_empty)
+    {
+        return Err(Status::BadRequest);
+    }
+    if !key.admin {
+        if let Err(err) = check_webhook_destination(&request.url).await {
+            warn!(
+                "Rejected the webhook of {} to {}: {}",
+                key.principal, request.url, err
+            );
+            return Err(Status::BadRequest);
+        }
+    }
+
+    match context
+        .webhooks
+        .register(&context.kubernetes_client, &key.principal, request.0)
+        .await
+    {
+        Ok(webhook) => Ok(status::Custom(Status::Created, Json(webhook))),
+        Err(err) => {
+            error!("Failed to register webhook: {}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// # List webhooks
+///
+/// This route lists the webhooks registered by the caller, or every webhook for
+/// admin principals.
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks")]
+pub async fn route_list_webhooks(
+    context: &State<crate::context::Context>,
+    key: ApiKey,
+) -> Json<ListWebhooksResponseModel> {
+    Json(
+        context
+            .webhooks
+            .list((!key.admin).then_some(key.principal.as_str())),
+    )
+}
+
+/// # Delete a webhook
+#[openapi(tag = "Webhooks")]
+#[delete("/webhooks/<id>")]
+pub async fn route_delete_webhook(
+    id: &str,
+    context: &State<crate::context::Context>,
+    key: ApiKey,
+) -> Status {
+    if let Err(status) = accessible_webhook(context, &key, id) {
+        return status;
+    }
+
+    match context
+        .webhooks
+        .remove(&context.kubernetes_client, id)
+        .await
+    {
+        Ok(()) => Status::NoContent,
+        Err(err) => {
+            error!("Failed to delete webhook {}: {}", id, err);
+            Status::InternalServerError
+        }
+    }
+}
+
+/// # List webhook deliveries
+///
+/// This route returns the most recent delivery attempts of the webhook, newest first.
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks/<id>/deliveries")]
+pub async fn route_list_webhook_deliveries(
+    id: &str,
+    context: &State<crate::context::Context>,
+    key: ApiKey,
+) -> Result<Json<ListWebhookDeliveriesResponseModel>, Status> {
+    accessible_webhook(context, &key, id)?;
+
+    Ok(Json(context.webhooks.deliveries(id)))
+}
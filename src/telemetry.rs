@@ -0,0 +1,113 @@
+use std::{fmt::Display, future::Future};
+
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    trace::{FutureExt, Status, TraceContextExt, TraceError, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+
+/// Install the W3C trace context propagator and, when an endpoint is configured,
+/// the OTLP exporter. Without an endpoint spans are still propagated but not exported.
+pub fn setup_tracing(otlp_endpoint: Option<&str>) -> Result<(), TraceError> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    if let Some(endpoint) = otlp_endpoint {
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                KeyValue::new("service.name", "moonscale"),
+                KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+            ])))
+            .install_batch(runtime::Tokio)?;
+    }
+    Ok(())
+}
+
+/// Flush pending spans before exiting.
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+/// Identifies the request being served, attached to the request context so log
+/// lines emitted while serving it can be correlated.
+#[derive(Clone)]
+pub struct RequestInfo {
+    pub request_id: String,
+    pub route: String,
+}
+
+pub fn current_request_info() -> Option<RequestInfo> {
+    Context::current().get::<RequestInfo>().cloned()
+}
+
+pub fn tracer() -> BoxedTracer {
+    global::tracer("moonscale")
+}
+
+/// The trace id of the span attached to the current context, if any.
+pub fn current_trace_id() -> Option<String> {
+    let context = Context::current();
+    let span_context = context.span().span_context().clone();
+
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
+
+/// Run `future` inside a child span of the current context, marking the span as
+/// failed when the future returns an error.
+pub async fn in_span<T, E: Display, F: Future<Output = Result<T, E>>>(
+    name: &'static str,
+    attributes: Vec<KeyValue>,
+    future: F,
+) -> Result<T, E> {
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &Context::current());
+    let span_context = Context::current_with_span(span);
+    let result = future.with_context(span_context.clone()).await;
+
+    if let Err(err) = &result {
+        span_context
+            .span()
+            .set_status(Status::error(err.to_string()));
+    }
+    span_context.span().end();
+    result
+}
+
+/// Synchronous counterpart of `in_span`.
+pub fn in_span_sync<T, E: Display>(
+    name: &'static str,
+    attributes: Vec<KeyValue>,
+    f: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &Context::current());
+    let span_context = Context::current_with_span(span);
+    let result = {
+        let _guard = span_context.clone().attach();
+
+        f()
+    };
+
+    if let Err(err) = &result {
+        span_context
+            .span()
+            .set_status(Status::error(err.to_string()));
+    }
+    span_context.span().end();
+    result
+}
@@ -0,0 +1,379 @@
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use k8s_openapi::serde_json;
+use kube::{
+    api::{DynamicObject, GroupVersionKind},
+    ResourceExt,
+};
+use regex::Regex;
+use serde::Deserialize;
+use tera::Tera;
+
+use crate::context::Config;
+use crate::metadata::InstanceMetadata;
+use crate::models::database::ResourcePreset;
+use crate::presets::preset_resources;
+use crate::profiles::{Profile, DEFAULT_PROFILE};
+
+pub fn multidoc_deserialize(
+    data: &str,
+    context: &mut tera::Context,
+) -> Result<Vec<serde_yaml::Value>, anyhow::Error> {
+    let mut docs = vec![];
+    let mut tera = Tera::default();
+
+    tera.add_raw_template("template", data)
+        .context("Rendering error")?;
+    let render_result = tera
+        .render("template", context)
+        .context("Failed to render template, check your yaml file.")?;
+
+    for de in serde_yaml::Deserializer::from_str(render_result.as_str()) {
+        let dedoc = serde_yaml::Value::deserialize(de)
+            .context("Couldn't deserialize yaml. Check format.")?;
+
+        // Documents can be left empty by a condition of the template
+        if !dedoc.is_null() {
+            docs.push(dedoc);
+        }
+    }
+    Ok(docs)
+}
+
+/// The time to live of an instance in minutes, the server default is capped by the
+/// maximum TTL so requests without one always fit in the quota.
+pub fn instance_ttl(config: &Config, ttl: Option<usize>) -> usize {
+    ttl.unwrap_or(match config.quota.max_ttl {
+        Some(max_ttl) => config.resource_ttl.min(max_ttl),
+        None => config.resource_ttl,
+    })
+}
+
+/// Minutes without connections before the instance is paused, 0 when it never is.
+pub fn instance_auto_pause(config: &Config, auto_pause: Option<usize>) -> usize {
+    auto_pause.or(config.auto_pause).unwrap_or(0)
+}
+
+/// Build the variables used to render the database template of an instance.
+pub fn instance_template_context(
+    config: &Config,
+    name: &str,
+    owner: &str,
+    root_password: &str,
+    profile: &Profile,
+    pvc_size: &str,
+    preset: ResourcePreset,
+) -> tera::Context {
+    let mut template_context: tera::Context = tera::Context::new();
+
+    template_context.insert("name", name);
+    template_context.insert("owner", owner);
+    template_context.insert("domain", config.ingress_domain.as_str());
+    template_context.insert("proxy", &config.proxy.is_some());
+    template_context.insert("resource_ttl", &config.resource_ttl);
+    template_context.insert("root_password", &BASE64_STANDARD.encode(root_password));
+    template_context.insert("pvc_size", pvc_size);
+    template_context.insert("storage_class", &profile.storage_class);
+    template_context.insert("preset", preset.as_str());
+    template_context.insert("resources", &preset_resources(preset));
+    template_context
+}
+
+/// Services run `moonscale-instance-<name>-headless`, which must fit in 63 characters.
+pub const MAX_INSTANCE_NAME_LENGTH: usize = 35;
+/// Names taken by static routes under `/database/`, an instance named like them
+/// couldn't be reached.
+const RESERVED_INSTANCE_NAMES: [&str; 1] = ["events"];
+
+/// The suffixes the template appends to `moonscale-instance-<name>`, eg: `-ps`.
+fn instance_name_suffixes(config: &Config, template_data: &str) -> Result<Vec<String>> {
+    let scoped_name = "moonscale-instance-probe";
+
+    Ok(rendered_instance_resources(config, template_data, "probe")?
+        .into_iter()
+        .filter_map(|resource| {
+            resource
+                .name
+                .strip_prefix(scoped_name)
+                .filter(|suffix| !suffix.is_empty())
+                .map(str::to_owned)
+        })
+        .collect())
+}
+
+/// Check that the name can be given to a new instance. It ends up in resource names,
+/// labels and the rendered YAML, so it must be a DNS-1123 label, and it can't end
+/// with a suffix of the template: `foo-ps` would take over the `-ps` Service of `foo`.
+pub fn validate_instance_name(
+    config: &Config,
+    template_data: &str,
+    name: &str,
+) -> Result<(), String> {
+    static DNS_LABEL: OnceLock<Regex> = OnceLock::new();
+    let dns_label =
+        DNS_LABEL.get_or_init(|| Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$").unwrap());
+
+    if name.len() > MAX_INSTANCE_NAME_LENGTH || !dns_label.is_match(name) {
+        return Err(format!(
+            "The name must be at most {} lowercase alphanumeric characters or '-', \
+starting and ending with an alphanumeric character",
+            MAX_INSTANCE_NAME_LENGTH
+        ));
+    }
+    if RESERVED_INSTANCE_NAMES.contains(&name) {
+        return Err(format!("The name {:?} is reserved", name));
+    }
+    let suffixes = instance_name_suffixes(config, template_data)
+        .map_err(|err| format!("Failed to render the template: {:#}", err))?;
+
+    match suffixes
+        .iter()
+        .find(|suffix| name.ends_with(suffix.as_str()))
+    {
+        Some(suffix) => Err(format!("The name can't end with {:?}", suffix)),
+        None => Ok(()),
+    }
+}
+
+/// Fail when a document isn't named after the instance: a fixed name would be shared
+/// by every instance, and deleting one instance would delete it for all of them.
+pub fn ensure_instance_scoped(instance_name: &str, doc: &serde_yaml::Value) -> Result<()> {
+    let kind = doc["kind"].as_str().unwrap_or("Resource");
+    let name = doc["metadata"]["name"].as_str().unwrap_or("");
+    let scoped_name = format!("moonscale-instance-{}", instance_name);
+
+    if name == scoped_name || name.starts_with(&format!("{}-", scoped_name)) {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "{} {:?} would collide across instances, its name must start with {:?}",
+        kind,
+        name,
+        scoped_name
+    ))
+}
+
+/// A resource produced by the template for a given instance.
+pub struct InstanceResource {
+    pub gvk: GroupVersionKind,
+    pub name: String,
+    pub namespace: Option<String>,
+}
+
+fn has_instance_label(metadata: &serde_json::Value, instance_name: &str) -> bool {
+    metadata["labels"]["app.kubernetes.io/instance"].as_str() == Some(instance_name)
+}
+
+/// The exact set of resources the rendered template produces for the instance,
+/// including the PersistentVolumeClaims created from StatefulSet `volumeClaimTemplates`.
+/// Documents that aren't labelled with the instance are skipped, as they may be
+/// shared with other instances, the system labels must be injected beforehand.
+pub fn instance_resources(
+    instance_name: &str,
+    docs: &[serde_yaml::Value],
+) -> Result<Vec<InstanceResource>, anyhow::Error> {
+    let mut resources = vec![];
+
+    for doc in docs {
+        let obj: DynamicObject = serde_yaml::from_value(doc.clone())?;
+        let type_meta = obj
+            .types
+            .as_ref()
+            .context("Document has no type metadata")?;
+        let gvk = GroupVersionKind::try_from(type_meta).context("Failed to get GVK")?;
+        let metadata = serde_json::to_value(&obj.metadata)?;
+
+        if !has_instance_label(&metadata, instance_name) {
+            continue;
+        }
+
+        let name = obj.name_any();
+        let replicas = obj.data["spec"]["replicas"].as_u64().unwrap_or(1);
+
+        if let Some(claim_templates) = obj.data["spec"]["volumeClaimTemplates"].as_array() {
+            for claim_template in claim_templates {
+                if !has_instance_label(&claim_template["metadata"], instance_name) {
+                    continue;
+                }
+                // PVCs created by a StatefulSet are named <claim>-<statefulset>-<ordinal>
+                let claim_name = claim_template["metadata"]["name"].as_str().unwrap_or("");
+
+                for ordinal in 0..replicas.max(1) {
+                    resources.push(InstanceResource {
+                        gvk: GroupVersionKind::gvk("", "v1", "PersistentVolumeClaim"),
+                        name: format!("{}-{}-{}", claim_name, name, ordinal),
+                        namespace: obj.metadata.namespace.clone(),
+                    });
+                }
+            }
+        }
+        resources.push(InstanceResource {
+            gvk,
+            name,
+            namespace: obj.metadata.namespace.clone(),
+        });
+    }
+    Ok(resources)
+}
+
+/// The API groups of the resources rendered by the template, the core group being
+/// `""`. Nothing is returned when the template doesn't render.
+pub fn template_api_groups(config: &Config, template_data: &str) -> Vec<String> {
+    let mut groups: Vec<String> = rendered_instance_resources(config, template_data, "probe")
+        .map(|resources| {
+            resources
+                .into_iter()
+                .map(|resource| resource.gvk.group)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    groups.sort();
+    groups.dedup();
+    groups
+}
+
+/// Render the template for the instance, with the system labels injected as on
+/// apply, to know exactly which resources it owns. It's rendered without proxy, so
+/// the Ingress of instances created before the proxy was enabled is included.
+pub fn rendered_instance_resources(
+    config: &Config,
+    template_data: &str,
+    instance_name: &str,
+) -> Result<Vec<InstanceResource>, anyhow::Error> {
+    let profile = &config.profiles[DEFAULT_PROFILE];
+    let mut template_context = instance_template_context(
+        config,
+        instance_name,
+        "",
+        "",
+        profile,
+        "1Gi",
+        profile.default_preset,
+    );
+    template_context.insert("proxy", &false);
+    let metadata = InstanceMetadata::default().with_system_metadata(
+        instance_name,
+        "",
+        &profile.name,
+        profile.default_preset,
+        instance_ttl(config, None),
+    );
+    let mut docs = multidoc_deserialize(template_data, &mut template_context)?;
+
+    for doc in docs.iter_mut() {
+        metadata.inject(doc);
+    }
+    instance_resources(instance_name, &docs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::profiles::load_profiles;
+    use crate::proxy::{ProxyConfig, ProxyMode};
+    use crate::quota::QuotaLimits;
+
+    const TEMPLATE: &str = include_str!("../resources/template.yml");
+
+    fn config() -> Config {
+        Config {
+            namespace: "moonscale".to_owned(),
+            ingress_domain: "example.com".to_owned(),
+            resource_ttl: 60,
+            api_keys: Default::default(),
+            admin_principals: vec![],
+            github_webhook_secret: None,
+            gitlab_webhook_token: None,
+            audit_sinks: vec![],
+            audit_history_size: 0,
+            health_cache_ttl: Duration::ZERO,
+            discovery_ttl: Duration::ZERO,
+            operation_retention: Duration::ZERO,
+            expiry_warning: Duration::ZERO,
+            quota: QuotaLimits::default(),
+            auto_pause: None,
+            activator: None,
+            proxy: None,
+            profiles: load_profiles(None, "standard").unwrap(),
+        }
+    }
+
+    #[test]
+    fn accepts_dns_labels() {
+        for name in [
+            "a",
+            "test",
+            "my-db-2",
+            &"a".repeat(MAX_INSTANCE_NAME_LENGTH),
+        ] {
+            assert_eq!(validate_instance_name(&config(), TEMPLATE, name), Ok(()));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        for name in [
+            "",
+            "-test",
+            "test-",
+            "Test",
+            "my_db",
+            "my.db",
+            "my db",
+            &"a".repeat(MAX_INSTANCE_NAME_LENGTH + 1),
+        ] {
+            assert!(
+                validate_instance_name(&config(), TEMPLATE, name).is_err(),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_names() {
+        assert!(validate_instance_name(&config(), TEMPLATE, "events")
+            .is_err_and(|err| err.contains("reserved")));
+        assert_eq!(
+            validate_instance_name(&config(), TEMPLATE, "events-2"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_template_suffixes() {
+        for name in ["foo-ps", "foo-headless"] {
+            assert!(
+                validate_instance_name(&config(), TEMPLATE, name)
+                    .is_err_and(|err| err.contains("can't end with")),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            validate_instance_name(&config(), TEMPLATE, "foo-psx"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rendered_resources_include_the_ingress_with_the_proxy() {
+        let mut config = config();
+
+        config.proxy = Some(ProxyConfig {
+            mode: ProxyMode::Path,
+            url: "https://moonscale.example.com".to_owned(),
+        });
+        let resources = rendered_instance_resources(&config, TEMPLATE, "test").unwrap();
+
+        assert!(resources
+            .iter()
+            .any(|resource| resource.gvk.kind == "Ingress"
+                && resource.name == "moonscale-instance-test"));
+    }
+}
@@ -0,0 +1,511 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use hmac::{Hmac, Mac};
+use k8s_openapi::{api::core::v1::Secret, serde_json, ByteString};
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams},
+    Api, Client,
+};
+use log::{error, info, warn};
+use rand::distributions::{Alphanumeric, DistString};
+use rocket::tokio::{self, net::lookup_host, sync::broadcast::error::RecvError, time::sleep};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::context::Context;
+use crate::metrics::metrics;
+use crate::models::{
+    lifecycle::{LifecycleEventModel, LifecycleEventType},
+    webhook::{CreateWebhookRequestModel, WebhookDeliveryModel, WebhookModel},
+};
+
+/// The Secret holding the registered webhooks, secrets included.
+const WEBHOOKS_SECRET_NAME: &str = "moonscale-webhooks";
+const WEBHOOKS_DATA_KEY: &str = "webhooks.json";
+const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+/// Base of the exponential backoff between two delivery attempts.
+const WEBHOOK_BACKOFF_BASE: Duration = Duration::from_secs(2);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many delivery attempts are kept per webhook.
+const WEBHOOK_DELIVERY_HISTORY_SIZE: usize = 50;
+
+const DEFAULT_EVENTS: [LifecycleEventType; 4] = [
+    LifecycleEventType::Ready,
+    LifecycleEventType::Failed,
+    LifecycleEventType::ExpiringSoon,
+    LifecycleEventType::Deleted,
+];
+
+#[derive(Clone, Serialize, Deserialize)]
+struct RegisteredWebhook {
+    #[serde(flatten)]
+    webhook: WebhookModel,
+    secret: String,
+}
+
+/// Outbound webhooks notified of the instance lifecycle events, persisted in a Secret.
+pub struct WebhookStore {
+    webhooks: Mutex<HashMap<String, RegisteredWebhook>>,
+    deliveries: Mutex<HashMap<String, VecDeque<WebhookDeliveryModel>>>,
+    /// Serializes the writes to the Secret so an older snapshot can't win.
+    persist_lock: tokio::sync::Mutex<()>,
+    http: reqwest::Client,
+}
+
+fn now_rfc3339() -> String {
+    humantime::format_rfc3339(SystemTime::now()).to_string()
+}
+
+/// `sha256=<hex HMAC-SHA256 of the body>`, as sent in `X-Moonscale-Signature`.
+fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether the address is reachable from the internet, rather than a loopback,
+/// private, link-local or otherwise internal address of the cluster.
+fn is_public_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [first, second, ..] = address.octets();
+
+            !(address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_documentation()
+                || address.is_multicast()
+                // "This network" 0.0.0.0/8
+                || first == 0
+                // Shared address space 100.64.0.0/10
+                || (first == 100 && (second & 0xc0) == 64)
+                // Reserved 240.0.0.0/4, broadcast included
+                || first >= 240)
+        }
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => is_public_address(IpAddr::V4(address)),
+            None => {
+                let segments = address.segments();
+
+                !(address.is_loopback()
+                    || address.is_unspecified()
+                    || address.is_multicast()
+                    // Unique local fc00::/7 and link-local fe80::/10
+                    || (segments[0] & 0xfe00) == 0xfc00
+                    || (segments[0] & 0xffc0) == 0xfe80
+                    // NAT64 64:ff9b::/96 translates to any IPv4 address
+                    || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0])
+            }
+        },
+    }
+}
+
+/// Resolve the host of the webhook URL, failing unless it is an http(s) URL whose
+/// host only resolves to public addresses, so the webhooks can't be used to reach
+/// the internal services.
+async fn resolve_public_destination(url: &str) -> Result<(String, Vec<SocketAddr>), String> {
+    let url = reqwest::Url::parse(url).map_err(|err| format!("Invalid URL: {}", err))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("The URL must use http or https".to_owned());
+    }
+    let host = url.host_str().ok_or("The URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    // IPv6 hosts are bracketed in URLs
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .map_err(|err| format!("Failed to resolve {}: {}", host, err))?
+        .collect();
+
+    if addresses.is_empty()
+        || !addresses
+            .iter()
+            .all(|address| is_public_address(address.ip()))
+    {
+        return Err(format!("{} isn't a public address", host));
+    }
+    Ok((host.to_owned(), addresses))
+}
+
+/// Check the destination of a webhook at registration.
+pub async fn check_webhook_destination(url: &str) -> Result<(), String> {
+    resolve_public_destination(url).await.map(|_| ())
+}
+
+fn http_client(builder: reqwest::ClientBuilder) -> reqwest::Client {
+    builder
+        .timeout(WEBHOOK_TIMEOUT)
+        // A redirect could point the delivery at an internal address
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Failed to build the webhooks HTTP client")
+}
+
+async fn load_webhooks(kubeclient: &Client) -> Result<Vec<RegisteredWebhook>, anyhow::Error> {
+    let api: Api<Secret> = Api::default_namespaced(kubeclient.clone());
+    let started = Instant::now();
+    let secret = api.get_opt(WEBHOOKS_SECRET_NAME).await;
+
+    metrics().observe_kubernetes_call("get", "Secret", started);
+    let data = secret?
+        .and_then(|secret| secret.data)
+        .and_then(|mut data| data.remove(WEBHOOKS_DATA_KEY));
+
+    match data {
+        Some(data) => Ok(serde_json::from_slice(&data.0)?),
+        None => Ok(vec![]),
+    }
+}
+
+impl WebhookStore {
+    /// Restore the webhooks registered by a previous run of the server.
+    pub async fn load(kubeclient: &Client) -> WebhookStore {
+        let webhooks = load_webhooks(kubeclient).await.unwrap_or_else(|err| {
+            error!("Failed to restore registered webhooks: {}", err);
+            vec![]
+        });
+
+        info!("Restored {} registered webhooks", webhooks.len());
+        WebhookStore {
+            webhooks: Mutex::new(
+                webhooks
+                    .into_iter()
+                    .map(|registered| (registered.webhook.id.clone(), registered))
+                    .collect(),
+            ),
+            deliveries: Mutex::new(HashMap::new()),
+            persist_lock: tokio::sync::Mutex::new(()),
+            http: http_client(reqwest::Client::builder()),
+        }
+    }
+
+    async fn persist(&self, kubeclient: &Client) -> Result<(), anyhow::Error> {
+        let _guard = self.persist_lock.lock().await;
+        let webhooks: Vec<RegisteredWebhook> =
+            self.webhooks.lock().unwrap().values().cloned().collect();
+        let secret = Secret {
+            metadata: ObjectMeta {
+                name: Some(WEBHOOKS_SECRET_NAME.to_owned()),
+                labels: Some(BTreeMap::from([(
+                    "app.kubernetes.io/managed-by".to_owned(),
+                    "Moonscale".to_owned(),
+                )])),
+                ..Default::default()
+            },
+            data: Some(BTreeMap::from([(
+                WEBHOOKS_DATA_KEY.to_owned(),
+                ByteString(serde_json::to_vec(&webhooks)?),
+            )])),
+            ..Default::default()
+        };
+        let api: Api<Secret> = Api::default_namespaced(kubeclient.clone());
+        let started = Instant::now();
+        let result = api
+            .patch(
+                WEBHOOKS_SECRET_NAME,
+                &PatchParams::apply("moonscale").force(),
+                &Patch::Apply(secret),
+            )
+            .await;
+
+        metrics().observe_kubernetes_call("apply", "Secret", started);
+        result?;
+        Ok(())
+    }
+
+    pub async fn register(
+        &self,
+        kubeclient: &Client,
+        principal: &str,
+        request: CreateWebhookRequestModel,
+    ) -> Result<WebhookModel, anyhow::Error> {
+        let webhook = WebhookModel {
+            id: Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 16)
+                .to_lowercase(),
+            url: request.url,
+            events: request.events.unwrap_or(DEFAULT_EVENTS.to_vec()),
+            principal: principal.to_owned(),
+            created_at: now_rfc3339(),
+        };
+
+        self.webhooks.lock().unwrap().insert(
+            webhook.id.clone(),
+            RegisteredWebhook {
+                webhook: webhook.clone(),
+                secret: request.secret,
+            },
+        );
+        if let Err(err) = self.persist(kubeclient).await {
+            self.webhooks.lock().unwrap().remove(&webhook.id);
+            return Err(err);
+        }
+        Ok(webhook)
+    }
+
+    /// The registered webhooks, only the ones of `principal` when set.
+    pub fn list(&self, principal: Option<&str>) -> Vec<WebhookModel> {
+        let mut webhooks: Vec<WebhookModel> = self
+            .webhooks
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|registered| principal.is_none_or(|p| registered.webhook.principal == p))
+            .map(|registered| registered.webhook.clone())
+            .collect();
+
+        webhooks.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        webhooks
+    }
+
+    pub fn get(&self, id: &str) -> Option<WebhookModel> {
+        self.webhooks
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|registered| registered.webhook.clone())
+    }
+
+    pub async fn remove(&self, kubeclient: &Client, id: &str) -> Result<(), anyhow::Error> {
+        let removed = self.webhooks.lock().unwrap().remove(id);
+
+        if let Err(err) = self.persist(kubeclient).await {
+            if let Some(removed) = removed {
+                self.webhooks.lock().unwrap().insert(id.to_owned(), removed);
+            }
+            return Err(err);
+        }
+        self.deliveries.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    /// The most recent delivery attempts of the webhook, newest first.
+    pub fn deliveries(&self, id: &str) -> Vec<WebhookDeliveryModel> {
+        self.deliveries
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|deliveries| deliveries.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn record_delivery(&self, webhook_id: &str, delivery: WebhookDeliveryModel) {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        let history = deliveries.entry(webhook_id.to_owned()).or_default();
+
+        if history.len() == WEBHOOK_DELIVERY_HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(delivery);
+    }
+
+    /// The client sending a delivery. The destination of the webhooks of non-admin
+    /// principals is resolved again and pinned for each attempt, so a DNS record
+    /// changed after the registration can't point it at an internal address.
+    async fn delivery_client(&self, url: &str, admin: bool) -> Result<reqwest::Client, String> {
+        if admin {
+            return Ok(self.http.clone());
+        }
+        let (host, addresses) = resolve_public_destination(url).await?;
+
+        Ok(http_client(
+            reqwest::Client::builder().resolve_to_addrs(&host, &addresses),
+        ))
+    }
+
+    /// Send the event to the webhook, retrying with an exponential backoff until the
+    /// receiver answers with a 2xx status.
+    async fn deliver(
+        &self,
+        registered: RegisteredWebhook,
+        admin: bool,
+        event: LifecycleEventModel,
+    ) {
+        let webhook = &registered.webhook;
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(err) => {
+                error!("Failed to serialize lifecycle event {}: {}", event.id, err);
+                return;
+            }
+        };
+        let signature = sign_payload(&registered.secret, &body);
+        let delivery_id = Alphanumeric
+            .sample_string(&mut rand::thread_rng(), 16)
+            .to_lowercase();
+
+        for attempt in 1..=WEBHOOK_MAX_ATTEMPTS {
+            let response = match self.delivery_client(&webhook.url, admin).await {
+                Ok(http) => http
+                    .post(&webhook.url)
+                    .header("Content-Type", "application/json")
+                    .header("X-Moonscale-Event", event.event.as_str())
+                    .header("X-Moonscale-Delivery", &delivery_id)
+                    .header("X-Moonscale-Signature", &signature)
+                    .body(body.clone())
+                    .send()
+                    .await
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err),
+            };
+            let (status_code, error) = match response {
+                Ok(response) if response.status().is_success() => {
+                    (Some(response.status().as_u16()), None)
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16()),
+                    Some(format!("Receiver answered {}", response.status())),
+                ),
+                Err(err) => (None, Some(err)),
+            };
+            let success = error.is_none();
+
+            self.record_delivery(
+                &webhook.id,
+                WebhookDeliveryModel {
+                    id: delivery_id.clone(),
+                    event_id: event.id,
+                    event: event.event,
+                    instance: event.instance.clone(),
+                    attempt,
+                    success,
+                    status_code,
+                    error: error.clone(),
+                    attempted_at: now_rfc3339(),
+                },
+            );
+            if success {
+                return;
+            }
+            warn!(
+                "Failed to deliver {} event of {} to webhook {}, attempt {}/{}: {}",
+                event.event.as_str(),
+                event.instance,
+                webhook.id,
+                attempt,
+                WEBHOOK_MAX_ATTEMPTS,
+                error.unwrap_or_default()
+            );
+            if attempt < WEBHOOK_MAX_ATTEMPTS {
+                sleep(WEBHOOK_BACKOFF_BASE * 2u32.pow(attempt - 1)).await;
+            }
+        }
+    }
+
+    /// The webhooks interested in the event, restricted to the ones whose principal
+    /// can access the instance, along with whether that principal is an admin.
+    fn subscribers(
+        &self,
+        event: &LifecycleEventModel,
+        admin_principals: &[String],
+    ) -> Vec<(RegisteredWebhook, bool)> {
+        self.webhooks
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|registered| registered.webhook.events.contains(&event.event))
+            .filter_map(|registered| {
+                let principal = &registered.webhook.principal;
+                let admin = admin_principals.contains(principal);
+
+                (admin || event.owner.as_ref() == Some(principal))
+                    .then(|| (registered.clone(), admin))
+            })
+            .collect()
+    }
+}
+
+/// Forward the lifecycle events to the registered webhooks.
+pub async fn dispatch_webhooks(context: Context) {
+    let (_, mut receiver) = context.lifecycle.subscribe(None);
+
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Webhooks missed {} lifecycle events", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        for (registered, admin) in context
+            .webhooks
+            .subscribers(&event, &context.config.admin_principals)
+        {
+            let context = context.clone();
+            let event = event.clone();
+
+            tokio::spawn(async move { context.webhooks.deliver(registered, admin, event).await });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(address: &str) -> bool {
+        is_public_address(address.parse().unwrap())
+    }
+
+    #[test]
+    fn accepts_public_addresses() {
+        assert!(public("93.184.216.34"));
+        assert!(public("2606:2800:220:1:248:1893:25c8:1946"));
+        assert!(public("::ffff:93.184.216.34"));
+    }
+
+    #[test]
+    fn rejects_internal_ipv4_addresses() {
+        for address in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "0.1.2.3",
+            "100.64.0.1",
+            "224.0.0.1",
+            "239.255.255.250",
+            "240.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(!public(address), "{}", address);
+        }
+    }
+
+    #[test]
+    fn rejects_internal_ipv6_addresses() {
+        for address in [
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "64:ff9b::a00:1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!public(address), "{}", address);
+        }
+    }
+
+    #[test]
+    fn signs_payloads() {
+        assert_eq!(
+            sign_payload("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}